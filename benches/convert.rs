@@ -0,0 +1,67 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use markdown_to_latex::{markdown_to_latex, LatexConverter, Tokenizer};
+
+/// A section exercising most constructs, repeated up to about a megabyte.
+const SECTION: &str = "\
+# Section title with *emphasis*
+
+Some running text with **bold**, *italic*, `code`, a [link](https://example.com/a_b)
+and an escaped \\* star, wrapped onto a second line with $x^2$ in it.
+A [reference][link-7] and a footnote.[^note-7]
+
+- first item
+- second item with ~~struck~~ text
+  1. nested *one*
+  2. nested two
+- [x] done task
+
+> A quoted paragraph
+> that continues here.
+
+| Name | Value |
+|:-----|------:|
+| a_b  | 10%   |
+
+```rust
+fn main() {
+    println!(\"hello\");
+}
+```
+
+";
+
+/// Link and footnote definitions appended to the document, so the cost of
+/// resolving references against a large set of them is measured too.
+const DEFINITIONS: usize = 2000;
+
+fn large_document() -> String {
+    let mut document = SECTION.repeat(1024 * 1024 / SECTION.len() + 1);
+    for index in 0..DEFINITIONS {
+        document.push_str(&format!(
+            "[link-{index}]: https://example.com/{index}\n[^note-{index}]: Note number {index}.\n"
+        ));
+    }
+    document
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let input = large_document();
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(20);
+    group.bench_function("tokenize", |b| b.iter(|| Tokenizer::new(&input).tokenize()));
+    group.bench_function("render", |b| {
+        b.iter_batched(
+            || Tokenizer::new(&input).tokenize(),
+            LatexConverter::convert,
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("markdown_to_latex", |b| {
+        b.iter(|| markdown_to_latex(&input))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+use std::fmt;
+
+use crate::tokenizer::Construct;
+
+/// Problems found in the Markdown source while converting it to LaTeX.
+#[derive(Debug, PartialEq)]
+pub enum ConvertError {
+    UnterminatedLink(String),
+    UnterminatedEmphasis(String),
+    UnterminatedCode(String),
+    UnterminatedCodeBlock(String),
+    UnsupportedHeaderLevel(u8),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::UnterminatedLink(text) => write!(f, "unterminated link: {}", text),
+            ConvertError::UnterminatedEmphasis(text) => {
+                write!(f, "unterminated emphasis: {}", text)
+            }
+            ConvertError::UnterminatedCode(text) => write!(f, "unterminated code span: {}", text),
+            ConvertError::UnterminatedCodeBlock(text) => {
+                write!(f, "unterminated code block: {}", text)
+            }
+            ConvertError::UnsupportedHeaderLevel(level) => {
+                write!(f, "header level {} has no LaTeX equivalent", level)
+            }
+        }
+    }
+}
+
+impl ConvertError {
+    pub(crate) fn unterminated(construct: &Construct, text: String) -> Self {
+        match construct {
+            Construct::Link => ConvertError::UnterminatedLink(text),
+            Construct::Emphasis => ConvertError::UnterminatedEmphasis(text),
+            Construct::Code => ConvertError::UnterminatedCode(text),
+            Construct::CodeBlock(_) => ConvertError::UnterminatedCodeBlock(text),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
@@ -1,5 +1,5 @@
-use std::fs::File;
-use std::io::{self, Read, Result, Write};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Result, Write};
 use std::path::Path;
 
 pub fn read_file_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
@@ -9,8 +9,32 @@ pub fn read_file_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
     Ok(content)
 }
 
+pub fn read_stdin_to_string() -> io::Result<String> {
+    let mut content = String::new();
+    io::stdin().read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Writes `latex` to `file_path`, creating any missing parent directories.
 pub fn write_to_file(latex: String, file_path: &str) -> Result<()> {
-    let mut file = File::create(file_path)?;
-    file.write_all(latex.as_bytes())?;
-    Ok(())
+    let mut file = create_file(file_path)?;
+    write_to_writer(&latex, &mut file)
+}
+
+/// Opens `file_path` for buffered writing, truncating any existing file
+/// and creating any missing parent directories.
+pub fn create_file_writer(file_path: &str) -> Result<BufWriter<File>> {
+    create_file(file_path).map(BufWriter::new)
+}
+
+fn create_file(file_path: &str) -> Result<File> {
+    if let Some(parent) = Path::new(file_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(file_path)
+}
+
+pub fn write_to_writer<W: Write>(latex: &str, writer: &mut W) -> Result<()> {
+    writer.write_all(latex.as_bytes())?;
+    writer.flush()
 }
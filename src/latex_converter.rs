@@ -1,90 +1,1880 @@
-use crate::tokenizer::Token;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::error::ConvertError;
+use crate::renderer::{self, Renderer};
+use crate::tokenizer::{Align, Construct, Numbering, Token};
+
+/// Settings for the preamble of a standalone document. Fields left as
+/// `None` are taken from the front matter, if the Markdown has one.
+pub struct DocumentOptions {
+    pub document_class: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+}
+
+impl Default for DocumentOptions {
+    fn default() -> Self {
+        DocumentOptions {
+            document_class: "article".to_string(),
+            title: None,
+            author: None,
+            date: None,
+        }
+    }
+}
+
+/// Settings that change how tokens are rendered.
+pub struct ConverterConfig {
+    /// The sectioning command for each header level, starting at level 1.
+    /// Levels without an entry are rendered in bold.
+    pub header_commands: Vec<String>,
+    /// Emits `\section*` and friends, so headers are neither numbered nor
+    /// listed in the table of contents.
+    pub starred_sections: bool,
+    pub typography: TypographyOptions,
+    pub link_style: LinkStyle,
+    /// Leaves HTML comments out of the output instead of keeping them as
+    /// LaTeX `%` comments.
+    pub drop_comments: bool,
+    /// The environments bullet and numbered lists are written in, for
+    /// example `compactitem` and `compactenum` from `paralist`. Any package
+    /// they need has to be loaded by the document.
+    pub itemize_env: String,
+    pub enumerate_env: String,
+    pub code_backend: CodeBackend,
+}
+
+/// The environment fenced code blocks with a language are written in.
+/// Blocks without a language are always `verbatim`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CodeBackend {
+    /// `verbatim`, dropping the language.
+    Verbatim,
+    /// `lstlisting` from `listings`, with `language=` set.
+    #[default]
+    Listings,
+    /// `minted`, which highlights with Pygments and needs LaTeX to be run
+    /// with `-shell-escape`.
+    Minted,
+}
+
+/// How links with their own text are rendered. Bare URLs are always
+/// printed with `\url`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LinkStyle {
+    /// `\href{url}{text}`: clickable text, for reading on screen.
+    #[default]
+    Href,
+    /// The text followed by `\footnote{\url{url}}`, so the target survives
+    /// printing.
+    Footnote,
+    /// `\url{url}` alone; the link text is dropped.
+    UrlOnly,
+}
+
+/// Typographic substitutions applied to running text. All of them are off
+/// by default, so the text comes through as written.
+#[derive(Default)]
+pub struct TypographyOptions {
+    /// Turns a hyphen with a space on each side into an en-dash, so
+    /// `1990 - 2000` becomes `1990 -- 2000`. Other hyphens are left alone.
+    pub smart_dashes: bool,
+    /// Turns straight quotes into LaTeX's curly ones, choosing opening or
+    /// closing from the character before: ``` ``like this'' ``` and
+    /// `` `like that' ``. Apostrophes, as in `don't`, stay closing quotes.
+    pub smart_quotes: bool,
+}
+
+impl Default for ConverterConfig {
+    fn default() -> Self {
+        ConverterConfig {
+            header_commands: [
+                "section",
+                "subsection",
+                "subsubsection",
+                "paragraph",
+                "subparagraph",
+            ]
+            .map(String::from)
+            .to_vec(),
+            starred_sections: false,
+            typography: TypographyOptions::default(),
+            link_style: LinkStyle::default(),
+            drop_comments: false,
+            itemize_env: "itemize".to_string(),
+            enumerate_env: "enumerate".to_string(),
+            code_backend: CodeBackend::default(),
+        }
+    }
+}
+
+/// Packages `convert_document` may load, in the order it loads them.
+const PACKAGES: [&str; 6] = [
+    "listings", "minted", "hyperref", "graphicx", "ulem", "amssymb",
+];
+
+/// Passes writes through while remembering the last byte, so the document
+/// end can be put on a line of its own.
+struct LastByte<'w, W> {
+    inner: &'w mut W,
+    last: Option<u8>,
+}
+
+impl<W: Write> Write for LastByte<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 pub struct LatexConverter {
-    in_list: bool,
-    list_type: Option<bool>,
+    config: ConverterConfig,
+    lists: Vec<(usize, bool)>, //(indent, is_numbered) of each open list
+    quote_depth: usize,
+    description: bool,           // whether a definition list is open
+    labels: HashSet<String>,     // figure labels handed out so far
+    previous_char: Option<char>, // last character of the running text, for smart quotes
 }
 
 impl LatexConverter {
+    pub fn with_config(config: ConverterConfig) -> Self {
+        LatexConverter {
+            config,
+            lists: Vec::new(),
+            quote_depth: 0,
+            description: false,
+            labels: HashSet::new(),
+            previous_char: None,
+        }
+    }
+
     pub fn convert(tokens: Vec<Token>) -> String {
-        let mut converter = LatexConverter {
-            in_list: false,
-            list_type: None,
-        };
-        let mut latex = String::new();
-        for token in tokens {
-            latex.push_str(&match token {
-                Token::Header(text, level) => {
-                    converter.close_list_if_needed() + &Self::convert_header(&text, level)
-                }
-                Token::Bold(text) => converter.close_list_if_needed() + &Self::convert_bold(&text),
-                Token::Italic(text) => {
-                    converter.close_list_if_needed() + &Self::convert_italic(&text)
-                }
-                Token::Link(text, url) => {
-                    converter.close_list_if_needed() + &Self::convert_link(&text, &url)
+        Self::convert_with(tokens, ConverterConfig::default())
+    }
+
+    pub fn convert_with(tokens: Vec<Token>, config: ConverterConfig) -> String {
+        Self::with_config(config).render(tokens)
+    }
+
+    /// Converts tokens as they arrive and writes the LaTeX straight to
+    /// `writer`, so a `Tokenizer` can be streamed without collecting it.
+    pub fn convert_to_writer<W: Write>(
+        tokens: impl IntoIterator<Item = Token>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        Self::with_config(ConverterConfig::default()).render_to_writer(tokens, writer)
+    }
+
+    /// Renders the tokens with this converter's configuration.
+    pub fn render(&mut self, tokens: impl IntoIterator<Item = Token>) -> String {
+        renderer::render(self, tokens)
+    }
+
+    pub fn render_to_writer<W: Write>(
+        &mut self,
+        tokens: impl IntoIterator<Item = Token>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        renderer::render_to_writer(self, tokens, writer)
+    }
+
+    /// Like `convert`, but fails on the first construct that cannot be
+    /// represented faithfully instead of rendering it as best it can.
+    pub fn convert_checked(tokens: Vec<Token>) -> Result<String, ConvertError> {
+        for token in Self::all_tokens(&tokens) {
+            match token {
+                Token::Unterminated(construct, text) => {
+                    return Err(ConvertError::unterminated(construct, text.clone()));
                 }
-                Token::ListItem(text, is_numbered) => {
-                    converter.convert_list_item(&text, is_numbered)
+                Token::Header(_, level) if *level > 6 => {
+                    return Err(ConvertError::UnsupportedHeaderLevel(*level));
                 }
-                Token::Text(text) => converter.close_list_if_needed() + &text,
-                Token::Newline => "\n".to_string(),
-            });
+                _ => {}
+            }
+        }
+        Ok(Self::convert(tokens))
+    }
+
+    /// Another name for `convert_checked`, returning the first error found.
+    pub fn try_convert(tokens: Vec<Token>) -> Result<String, ConvertError> {
+        Self::convert_checked(tokens)
+    }
+
+    /// Converts the tokens into a standalone document with a preamble that
+    /// loads only the packages the body actually needs.
+    pub fn convert_document(tokens: Vec<Token>, options: DocumentOptions) -> String {
+        Self::convert_document_with(tokens, options, ConverterConfig::default())
+    }
+
+    /// Like `convert_document`, rendering the body with the given
+    /// configuration.
+    pub fn convert_document_with(
+        tokens: Vec<Token>,
+        options: DocumentOptions,
+        config: ConverterConfig,
+    ) -> String {
+        let mut options = options;
+        for token in &tokens {
+            Self::note_front_matter(token, &mut options);
+        }
+        let packages = Self::required_packages(&tokens, config.code_backend);
+        let mut document = Self::preamble(&packages, &options);
+        renderer::render_into(&mut Self::with_config(config), tokens, &mut document);
+        if !document.ends_with('\n') {
+            document.push('\n');
+        }
+        document + "\\end{document}\n"
+    }
+
+    /// Writes a standalone document to `writer` while the tokens are being
+    /// converted. The preamble has to name its packages and metadata before
+    /// the body, so the stream is cloned and read twice: once to find them
+    /// and once to render, which keeps memory bounded for a `Tokenizer`.
+    pub fn convert_document_to_writer<W: Write>(
+        tokens: impl IntoIterator<Item = Token> + Clone,
+        options: DocumentOptions,
+        config: ConverterConfig,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let mut options = options;
+        let mut needed = Vec::new();
+        for token in tokens.clone() {
+            Self::note_front_matter(&token, &mut options);
+            Self::note_packages(&token, config.code_backend, &mut needed);
+        }
+        let packages = Self::in_load_order(needed);
+        writer.write_all(Self::preamble(&packages, &options).as_bytes())?;
+        let mut body = LastByte {
+            inner: writer,
+            last: None,
+        };
+        Self::with_config(config).render_to_writer(tokens, &mut body)?;
+        if body.last.is_some_and(|last| last != b'\n') {
+            writer.write_all(b"\n")?;
         }
-        latex + &converter.close_list_if_needed()
+        writer.write_all(b"\\end{document}\n")
     }
 
-    fn convert_header(text: &str, level: u8) -> String {
-        match level {
-            1 => format!("\\section{{{}}}\n", text),
-            2 => format!("\\subsection{{{}}}\n", text),
-            3 => format!("\\subsubsection{{{}}}\n", text),
-            4 => format!("\\paragraph{{{}}}\n", text),
-            5 => format!("\\subparagraph{{{}}}\n", text),
-            _ => format!("\\textbf{{{}}}\n", text),
+    /// Every token in the stream, including the inline tokens nested inside
+    /// headers, list items and block quotes.
+    fn all_tokens<'t>(tokens: impl IntoIterator<Item = &'t Token>) -> Vec<&'t Token> {
+        let mut all = Vec::new();
+        for token in tokens {
+            all.push(token);
+            all.extend(Self::all_tokens(token.children()));
         }
+        all
     }
 
-    fn convert_bold(text: &str) -> String {
-        format!("\\textbf{{{}}}", text)
+    fn required_packages(tokens: &[Token], code_backend: CodeBackend) -> Vec<&'static str> {
+        let mut needed = Vec::new();
+        for token in tokens {
+            Self::note_packages(token, code_backend, &mut needed);
+        }
+        Self::in_load_order(needed)
     }
 
-    fn convert_italic(text: &str) -> String {
-        format!("\\textit{{{}}}", text)
+    /// Records the packages `token` and anything nested in it rely on.
+    fn note_packages(token: &Token, code_backend: CodeBackend, needed: &mut Vec<&'static str>) {
+        let package = match token {
+            Token::CodeBlock(_, Some(_))
+            | Token::Unterminated(Construct::CodeBlock(Some(_)), _) => match code_backend {
+                CodeBackend::Verbatim => None,
+                CodeBackend::Listings => Some("listings"),
+                CodeBackend::Minted => Some("minted"),
+            },
+            Token::Link(..) => Some("hyperref"),
+            Token::Image(..) => Some("graphicx"),
+            Token::Strikethrough(..) => Some("ulem"),
+            Token::TaskItem(..) => Some("amssymb"),
+            _ => None,
+        };
+        if let Some(package) = package.filter(|package| !needed.contains(package)) {
+            needed.push(package);
+        }
+        for child in token.children() {
+            Self::note_packages(child, code_backend, needed);
+        }
     }
 
-    fn convert_link(text: &str, url: &str) -> String {
-        format!("\\href{{{}}}{{{}}}", url, text)
+    /// Fills in the title, author and date the caller left unset from a
+    /// front matter block.
+    fn note_front_matter(token: &Token, options: &mut DocumentOptions) {
+        let Token::FrontMatter(fields) = token else {
+            return;
+        };
+        for (key, value) in fields {
+            let field = match key.as_str() {
+                "title" => &mut options.title,
+                "author" => &mut options.author,
+                "date" => &mut options.date,
+                _ => continue,
+            };
+            field.get_or_insert_with(|| value.clone());
+        }
     }
 
-    fn convert_list_item(&mut self, text: &str, is_numbered: bool) -> String {
-        if !self.in_list {
-            self.in_list = true;
-            self.list_type = Some(is_numbered);
-            let env = if is_numbered { "enumerate" } else { "itemize" };
-            format!("\\begin{{{}}}\n\\item {}", env, text)
-        } else if self.list_type == Some(is_numbered) {
-            format!("\\item {}", text)
+    fn in_load_order(needed: Vec<&'static str>) -> Vec<&'static str> {
+        PACKAGES
+            .into_iter()
+            .filter(|package| needed.contains(package))
+            .collect()
+    }
+
+    /// Everything from `\documentclass` up to the start of the body.
+    fn preamble(packages: &[&str], options: &DocumentOptions) -> String {
+        let mut preamble = format!("\\documentclass{{{}}}\n", options.document_class);
+        for package in packages {
+            preamble.push_str(&format!("\\usepackage{{{}}}\n", package));
+        }
+        if let Some(title) = &options.title {
+            preamble.push_str(&format!("\\title{{{}}}\n", escape_latex(title)));
+        }
+        if let Some(author) = &options.author {
+            preamble.push_str(&format!("\\author{{{}}}\n", escape_latex(author)));
+        }
+        if let Some(date) = &options.date {
+            preamble.push_str(&format!("\\date{{{}}}\n", escape_latex(date)));
+        }
+        preamble.push_str("\\begin{document}\n");
+        if options.title.is_some() {
+            preamble.push_str("\\maketitle\n");
+        }
+        preamble
+    }
+
+    /// A `fig:` label built from the alt text, numbered when another figure
+    /// already took it so every `\ref` has exactly one target.
+    fn figure_label(&mut self, alt: &str) -> String {
+        let slug = slugify(alt);
+        let base = if slug.is_empty() {
+            "fig:figure".to_string()
         } else {
-            let close = self.close_list_if_needed();
-            self.in_list = true;
-            self.list_type = Some(is_numbered);
-            let env = if is_numbered { "enumerate" } else { "itemize" };
-            format!("{}\\begin{{{}}}\n\\item {}", close, env, text)
+            format!("fig:{}", slug)
+        };
+        let mut label = base.clone();
+        let mut counter = 1;
+        while !self.labels.insert(label.clone()) {
+            counter += 1;
+            label = format!("{}-{}", base, counter);
         }
+        label
     }
 
-    fn close_list_if_needed(&mut self) -> String {
-        if self.in_list {
-            self.in_list = false;
-            let env = if self.list_type.unwrap_or(false) {
-                "enumerate"
-            } else {
-                "itemize"
-            };
-            format!("\\end{{{}}}\n", env)
+    /// A quote opens after whitespace, an opening bracket or another
+    /// opening quote, and closes anywhere else.
+    fn smart_quotes(&mut self, text: &str) -> String {
+        let mut quoted = String::with_capacity(text.len());
+        for ch in text.chars() {
+            let opening = self
+                .previous_char
+                .is_none_or(|previous| previous.is_whitespace() || "([{`".contains(previous));
+            match (ch, opening) {
+                ('"', true) => quoted.push_str("``"),
+                ('"', false) => quoted.push_str("''"),
+                ('\'', true) => quoted.push('`'),
+                _ => quoted.push(ch),
+            }
+            self.previous_char = quoted.chars().last();
+        }
+        quoted
+    }
+
+    /// Opens and closes list environments so that an `\item` at `indent`
+    /// can follow, as the nesting changes.
+    fn convert_list_item(
+        &mut self,
+        out: &mut String,
+        number: Option<(u32, Numbering)>,
+        indent: usize,
+    ) {
+        let is_numbered = number.is_some();
+        self.close_description(out);
+        while self
+            .lists
+            .last()
+            .is_some_and(|&(open_indent, _)| open_indent > indent)
+        {
+            self.close_innermost_list(out);
+        }
+        let same_level = self
+            .lists
+            .last()
+            .filter(|&&(open_indent, _)| open_indent == indent);
+        match same_level {
+            Some(&(_, open_numbered)) if open_numbered == is_numbered => {}
+            Some(_) => {
+                self.close_innermost_list(out);
+                self.open_list(out, indent, number);
+            }
+            None => self.open_list(out, indent, number),
+        }
+    }
+
+    fn open_list(&mut self, out: &mut String, indent: usize, number: Option<(u32, Numbering)>) {
+        let is_numbered = number.is_some();
+        self.lists.push((indent, is_numbered));
+        let _ = writeln!(out, "\\begin{{{}}}", self.list_env(is_numbered));
+        if let Some((start, numbering)) = number {
+            self.label_style(out, numbering);
+            self.start_counter(out, start);
+        }
+    }
+
+    fn item_line(out: &mut String, label: Option<&str>, content: &str) {
+        match label {
+            Some(label) => {
+                let _ = write!(out, "\\item[{}] {}", label, content);
+            }
+            None => {
+                out.push_str("\\item ");
+                out.push_str(content);
+            }
+        }
+    }
+
+    /// Makes an ordered list that does not start at 1 begin at the
+    /// Markdown start number.
+    fn start_counter(&self, out: &mut String, start: u32) {
+        if start == 1 {
+            return;
+        }
+        let _ = writeln!(
+            out,
+            "\\setcounter{{{}}}{{{}}}",
+            self.enum_counter(),
+            i64::from(start) - 1
+        );
+    }
+
+    /// Relabels the innermost ordered list when its Markdown markers were
+    /// letters or roman numerals; the change ends with the environment.
+    fn label_style(&self, out: &mut String, numbering: Numbering) {
+        let command = match numbering {
+            Numbering::Decimal => return,
+            Numbering::LowerAlpha => "alph",
+            Numbering::UpperAlpha => "Alph",
+            Numbering::LowerRoman => "roman",
+            Numbering::UpperRoman => "Roman",
+        };
+        let counter = self.enum_counter();
+        let _ = writeln!(
+            out,
+            "\\renewcommand{{\\label{}}}{{\\{}{{{}}}.}}",
+            counter, command, counter
+        );
+    }
+
+    /// The counter of the innermost open ordered list.
+    fn enum_counter(&self) -> &'static str {
+        let depth = self.lists.iter().filter(|&&(_, numbered)| numbered).count();
+        match depth {
+            1 => "enumi",
+            2 => "enumii",
+            3 => "enumiii",
+            _ => "enumiv",
+        }
+    }
+
+    fn convert_table_row(out: &mut String, cells: &[String]) {
+        for (index, cell) in cells.iter().enumerate() {
+            if index > 0 {
+                out.push_str(" & ");
+            }
+            push_escaped_latex(out, cell);
+        }
+        out.push_str(" \\\\\n");
+    }
+
+    fn list_env(&self, is_numbered: bool) -> &str {
+        if is_numbered {
+            &self.config.enumerate_env
+        } else {
+            &self.config.itemize_env
+        }
+    }
+
+    fn close_quote_if_needed(&mut self, out: &mut String) {
+        self.close_quotes_deeper_than(out, 0);
+    }
+
+    fn close_quotes_deeper_than(&mut self, out: &mut String, depth: usize) {
+        while self.quote_depth > depth {
+            self.quote_depth -= 1;
+            out.push_str("\\end{quote}\n");
+        }
+    }
+
+    fn close_list_if_needed(&mut self, out: &mut String) {
+        self.close_description(out);
+        while !self.lists.is_empty() {
+            self.close_innermost_list(out);
+        }
+    }
+
+    fn close_description(&mut self, out: &mut String) {
+        if std::mem::take(&mut self.description) {
+            out.push_str("\\end{description}\n");
+        }
+    }
+
+    fn close_innermost_list(&mut self, out: &mut String) {
+        if let Some((_, is_numbered)) = self.lists.pop() {
+            let _ = writeln!(out, "\\end{{{}}}", self.list_env(is_numbered));
+        }
+    }
+}
+
+impl Renderer for LatexConverter {
+    fn render_text(&mut self, out: &mut String, text: &str) {
+        let quoted;
+        let text = if self.config.typography.smart_quotes {
+            quoted = self.smart_quotes(text);
+            &quoted
+        } else {
+            text
+        };
+        if self.config.typography.smart_dashes {
+            out.push_str(&escape_latex(text).replace(" - ", " -- "));
+        } else {
+            push_escaped_latex(out, text);
+        }
+    }
+
+    /// Markdown stops at six levels, so deeper headers are rendered as
+    /// level 6; `convert_checked` reports them as errors instead.
+    fn render_header(&mut self, out: &mut String, content: &str, level: u8) {
+        let command = usize::from(level.min(6))
+            .checked_sub(1)
+            .and_then(|index| self.config.header_commands.get(index));
+        let _ = match command {
+            Some(command) if self.config.starred_sections => {
+                writeln!(out, "\\{}*{{{}}}", command, content)
+            }
+            Some(command) => writeln!(out, "\\{}{{{}}}", command, content),
+            None => writeln!(out, "\\textbf{{{}}}", content),
+        };
+    }
+
+    fn render_bold(&mut self, out: &mut String, content: &str) {
+        let _ = write!(out, "\\textbf{{{}}}", content);
+    }
+
+    fn render_italic(&mut self, out: &mut String, content: &str) {
+        let _ = write!(out, "\\textit{{{}}}", content);
+    }
+
+    fn render_bold_italic(&mut self, out: &mut String, content: &str) {
+        let _ = write!(out, "\\textbf{{\\textit{{{}}}}}", content);
+    }
+
+    // `\sout` comes from `ulem`, which `convert_document` loads on demand.
+    fn render_strikethrough(&mut self, out: &mut String, content: &str) {
+        let _ = write!(out, "\\sout{{{}}}", content);
+    }
+
+    // LaTeX has nowhere to show a hover title, and a comment would break
+    // the line inside a command argument, so the title is left out.
+    fn render_link(&mut self, out: &mut String, content: &str, url: &str, _title: Option<&str>) {
+        let url = escape_url(url);
+        let _ = match self.config.link_style {
+            LinkStyle::Href => write!(out, "\\href{{{}}}{{{}}}", url, content),
+            LinkStyle::Footnote => write!(out, "{}\\footnote{{\\url{{{}}}}}", content, url),
+            LinkStyle::UrlOnly => write!(out, "\\url{{{}}}", url),
+        };
+    }
+
+    fn render_url(&mut self, out: &mut String, url: &str) {
+        let _ = write!(out, "\\url{{{}}}", escape_url(url));
+    }
+
+    // A path is otherwise passed as written, but an unescaped `%` would
+    // comment out the rest of the line.
+    fn render_image(&mut self, out: &mut String, alt: &str, path: &str) {
+        let _ = writeln!(
+            out,
+            "\\begin{{figure}}[h]\n\\centering\n\\includegraphics{{{}}}",
+            path.replace('%', "\\%")
+        );
+        if !alt.is_empty() {
+            let _ = writeln!(
+                out,
+                "\\caption{{{}}}\n\\label{{{}}}",
+                escape_latex(alt),
+                self.figure_label(alt)
+            );
+        }
+        out.push_str("\\end{figure}\n");
+    }
+
+    fn render_footnote(&mut self, out: &mut String, content: &str) {
+        let _ = write!(out, "\\footnote{{{}}}", content);
+    }
+
+    fn render_list_item(
+        &mut self,
+        out: &mut String,
+        content: &str,
+        number: Option<(u32, Numbering)>,
+        indent: usize,
+    ) {
+        self.convert_list_item(out, number, indent);
+        Self::item_line(out, None, content);
+    }
+
+    // `\square` and `\boxtimes` come from `amssymb`.
+    fn render_task_item(&mut self, out: &mut String, content: &str, checked: bool, indent: usize) {
+        let glyph = if checked {
+            "$\\boxtimes$"
         } else {
-            String::new()
+            "$\\square$"
+        };
+        self.convert_list_item(out, None, indent);
+        Self::item_line(out, Some(glyph), content);
+    }
+
+    fn render_blockquote(&mut self, out: &mut String, content: &str, depth: usize) {
+        self.close_quotes_deeper_than(out, depth);
+        while self.quote_depth < depth {
+            self.quote_depth += 1;
+            out.push_str("\\begin{quote}\n");
+        }
+        out.push_str(content);
+    }
+
+    fn render_code(&mut self, out: &mut String, code: &str) {
+        out.push_str("\\texttt{");
+        push_escaped_latex(out, code);
+        out.push('}');
+    }
+
+    // Formulas are already LaTeX, so they pass through unescaped.
+    fn render_inline_math(&mut self, out: &mut String, formula: &str) {
+        let _ = write!(out, "${}$", formula);
+    }
+
+    fn render_display_math(&mut self, out: &mut String, formula: &str) {
+        let _ = write!(out, "\\[{}\\]", formula);
+    }
+
+    fn render_raw_latex(&mut self, out: &mut String, latex: &str) {
+        out.push_str(latex);
+        out.push('\n');
+    }
+
+    fn render_code_block(&mut self, out: &mut String, code: &str, language: Option<&str>) {
+        let end = match (language, self.config.code_backend) {
+            (Some(language), CodeBackend::Listings) => {
+                let _ = write!(out, "\\begin{{lstlisting}}[language={}]", language);
+                "\\end{lstlisting}"
+            }
+            (Some(language), CodeBackend::Minted) => {
+                let _ = write!(out, "\\begin{{minted}}{{{}}}", language);
+                "\\end{minted}"
+            }
+            _ => {
+                out.push_str("\\begin{verbatim}");
+                "\\end{verbatim}"
+            }
+        };
+        out.push('\n');
+        if !code.is_empty() {
+            out.push_str(code);
+            out.push('\n');
+        }
+        out.push_str(end);
+        out.push('\n');
+    }
+
+    fn render_table(
+        &mut self,
+        out: &mut String,
+        headers: &[String],
+        rows: &[Vec<String>],
+        alignments: &[Align],
+    ) {
+        out.push_str("\\begin{tabular}{");
+        out.extend(alignments.iter().map(|alignment| match alignment {
+            Align::Left => 'l',
+            Align::Center => 'c',
+            Align::Right => 'r',
+        }));
+        out.push_str("}\n\\hline\n");
+        Self::convert_table_row(out, headers);
+        out.push_str("\\hline\n");
+        for row in rows {
+            Self::convert_table_row(out, row);
+        }
+        out.push_str("\\hline\n\\end{tabular}\n");
+    }
+
+    fn render_definition_item(&mut self, out: &mut String, term: &str, definitions: &[String]) {
+        if !self.description {
+            self.close_list_if_needed(out);
+            out.push_str("\\begin{description}\n");
+            self.description = true;
+        }
+        // Only the first definition carries the term; the others follow
+        // under it with an empty label.
+        let mut label = Some(term);
+        for (index, definition) in definitions.iter().enumerate() {
+            if index > 0 {
+                out.push('\n');
+            }
+            Self::item_line(out, label.take(), definition);
+        }
+    }
+
+    fn render_hard_break(&mut self, out: &mut String) {
+        self.previous_char = None;
+        out.push_str("\\\\\n");
+    }
+
+    fn render_paragraph_break(&mut self, out: &mut String) {
+        self.previous_char = None;
+        out.push_str("\n\n");
+    }
+
+    fn render_horizontal_rule(&mut self, out: &mut String) {
+        out.push_str("\\noindent\\rule{\\linewidth}{0.4pt}\n");
+    }
+
+    fn render_newline(&mut self, out: &mut String) {
+        self.previous_char = None;
+        out.push('\n');
+    }
+
+    // Every line gets its own `%`, and the line break after the last one
+    // keeps the comment from swallowing whatever follows it.
+    fn render_comment(&mut self, out: &mut String, text: &str) {
+        if self.config.drop_comments {
+            return;
+        }
+        for line in text.lines() {
+            let _ = match line.trim() {
+                "" => writeln!(out, "%"),
+                line => writeln!(out, "% {}", line),
+            };
+        }
+    }
+
+    /// Divs with a class naming an alignment become that environment; any
+    /// other class is dropped and the content kept.
+    fn render_div(&mut self, out: &mut String, class: &str, content: &str) {
+        let environment = match class {
+            "center" => "center",
+            "left" => "flushleft",
+            "right" => "flushright",
+            _ => return out.push_str(content),
+        };
+        let _ = writeln!(
+            out,
+            "\\begin{{{}}}\n{}\n\\end{{{}}}",
+            environment,
+            content.trim_end_matches('\n'),
+            environment
+        );
+    }
+
+    fn end_lists(&mut self, out: &mut String) {
+        self.close_list_if_needed(out);
+    }
+
+    fn end_quotes(&mut self, out: &mut String) {
+        self.close_quote_if_needed(out);
+    }
+}
+
+/// Escapes the characters that have a special meaning in LaTeX so that
+/// arbitrary Markdown text can be placed in the document body.
+pub fn escape_latex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    push_escaped_latex(&mut escaped, text);
+    escaped
+}
+
+/// Appends `text` to `out` escaped as by `escape_latex`.
+fn push_escaped_latex(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '\\' => out.push_str("\\textbackslash{}"),
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            _ => out.push(ch),
+        }
+    }
+}
+
+/// Lowercases `text` and joins its words with hyphens, dropping anything
+/// that is not safe inside a `\\label`.
+fn slugify(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|ch| ch.is_alphanumeric() || *ch == '-')
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Escapes a URL for `\\url` and `\\href`. Those commands read most
+/// characters literally, but `%` and `#` still need a backslash so the
+/// argument survives being passed through other macros.
+pub fn escape_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for ch in url.chars() {
+        if matches!(ch, '%' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    fn convert(input: &str) -> String {
+        let mut tokenizer = Tokenizer::new(input);
+        LatexConverter::convert(tokenizer.tokenize())
+    }
+
+    #[test]
+    fn test_header_levels_above_six_are_clamped() {
+        let config = ConverterConfig {
+            header_commands: (1..=7).map(|level| format!("h{}", level)).collect(),
+            ..ConverterConfig::default()
+        };
+        let mut converter = LatexConverter::with_config(config);
+        let mut tokenizer = Tokenizer::new("###### Six\n####### Seven");
+        assert_eq!(
+            converter.render(tokenizer.tokenize()),
+            "\\h6{Six}\n\n\\h6{Seven}\n"
+        );
+        assert_eq!(convert("####### Seven"), "\\textbf{Seven}\n");
+    }
+
+    #[test]
+    fn test_empty_header_renders_nothing() {
+        assert_eq!(convert("#\ntext"), "\ntext");
+    }
+
+    #[test]
+    fn test_starred_sections() {
+        let config = ConverterConfig {
+            starred_sections: true,
+            ..ConverterConfig::default()
+        };
+        let mut tokenizer = Tokenizer::new("# Title\n## Part\n###### Deep");
+        assert_eq!(
+            LatexConverter::convert_with(tokenizer.tokenize(), config),
+            "\\section*{Title}\n\n\\subsection*{Part}\n\n\\textbf{Deep}\n"
+        );
+    }
+
+    #[test]
+    fn test_configured_header_commands() {
+        let config = ConverterConfig {
+            header_commands: vec!["chapter".to_string(), "section".to_string()],
+            ..ConverterConfig::default()
+        };
+        let mut converter = LatexConverter::with_config(config);
+        let mut tokenizer = Tokenizer::new("# Intro\n## Scope\n### Details");
+        assert_eq!(
+            converter.render(tokenizer.tokenize()),
+            "\\chapter{Intro}\n\n\\section{Scope}\n\n\\textbf{Details}\n"
+        );
+    }
+
+    #[test]
+    fn test_streaming_matches_buffered_output() {
+        let input = "# Title\nSome *text*\nwrapped.\n\n- one\n  1. two\n> quote\n\n```rust\nfn main() {}\n```";
+        let mut streamed = Vec::new();
+        LatexConverter::convert_to_writer(Tokenizer::new(input), &mut streamed).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), convert(input));
+    }
+
+    #[test]
+    fn test_document_streamed_to_writer_matches_buffered_document() {
+        let input =
+            "# Intro\n- [x] see [docs](http://example.com)\n\n```rust\nlet x = 1;\n```\nend";
+        let options = || DocumentOptions {
+            title: Some("Notes".to_string()),
+            ..DocumentOptions::default()
+        };
+        let mut streamed = Vec::new();
+        LatexConverter::convert_document_to_writer(
+            Tokenizer::new(input),
+            options(),
+            ConverterConfig::default(),
+            &mut streamed,
+        )
+        .unwrap();
+        let buffered =
+            LatexConverter::convert_document(Tokenizer::new(input).tokenize(), options());
+        assert_eq!(String::from_utf8(streamed).unwrap(), buffered);
+    }
+
+    #[test]
+    fn test_convert_to_writer_fills_a_byte_buffer() {
+        let mut output: Vec<u8> = Vec::new();
+        LatexConverter::convert_to_writer(Tokenizer::new("**a** and *b*"), &mut output).unwrap();
+        assert_eq!(output, convert("**a** and *b*").into_bytes());
+    }
+
+    #[test]
+    fn test_convert_document() {
+        let mut tokenizer = Tokenizer::new("# Intro\nSee [docs](http://example.com).");
+        let options = DocumentOptions {
+            title: Some("Notes & Ideas".to_string()),
+            author: Some("Jane".to_string()),
+            ..DocumentOptions::default()
+        };
+        let latex = LatexConverter::convert_document(tokenizer.tokenize(), options);
+        assert!(latex.starts_with("\\documentclass{article}\n"));
+        assert!(latex.contains("\\usepackage{hyperref}\n"));
+        assert!(latex.contains("\\title{Notes \\& Ideas}\n\\author{Jane}\n"));
+        assert!(latex.contains("\\begin{document}\n\\maketitle\n\\section{Intro}\n"));
+        assert!(latex.ends_with("\\end{document}\n"));
+    }
+
+    #[test]
+    fn test_front_matter_fills_document_metadata() {
+        let input = "---\ntitle: \"Notes & Ideas\"\nauthor: Jane\ndate: 2024-01-05\ntags: [a, b]\n---\nBody";
+        let options = DocumentOptions {
+            author: Some("Sam".to_string()),
+            ..DocumentOptions::default()
+        };
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            LatexConverter::convert_document(tokenizer.tokenize(), options),
+            "\\documentclass{article}\n\\title{Notes \\& Ideas}\n\\author{Sam}\n\\date{2024-01-05}\n\\begin{document}\n\\maketitle\nBody\n\\end{document}\n"
+        );
+    }
+
+    #[test]
+    fn test_document_preamble_appears_once() {
+        let mut tokenizer = Tokenizer::new("# A\n\nText with [a link](http://example.com)");
+        let latex =
+            LatexConverter::convert_document(tokenizer.tokenize(), DocumentOptions::default());
+        assert_eq!(latex.matches("\\documentclass").count(), 1);
+        assert_eq!(latex.matches("\\begin{document}").count(), 1);
+        assert_eq!(latex.matches("\\end{document}").count(), 1);
+        assert_eq!(latex.matches("\\usepackage{hyperref}").count(), 1);
+    }
+
+    #[test]
+    fn test_document_without_links_skips_hyperref() {
+        let mut tokenizer = Tokenizer::new("Just text");
+        let latex =
+            LatexConverter::convert_document(tokenizer.tokenize(), DocumentOptions::default());
+        assert_eq!(
+            latex,
+            "\\documentclass{article}\n\\begin{document}\nJust text\n\\end{document}\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_checked_reports_unterminated_link() {
+        let mut tokenizer = Tokenizer::new("See [link](...");
+        assert_eq!(
+            LatexConverter::convert_checked(tokenizer.tokenize()),
+            Err(ConvertError::UnterminatedLink("[link](...".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_try_convert_reports_errors_or_converts() {
+        let tokens = Tokenizer::new("# Title\n[broken").tokenize();
+        assert_eq!(
+            LatexConverter::try_convert(tokens),
+            Err(ConvertError::UnterminatedLink("[broken".to_string()))
+        );
+        let tokens = Tokenizer::new("# Title").tokenize();
+        assert_eq!(
+            LatexConverter::try_convert(tokens),
+            Ok("\\section{Title}\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_convert_checked_reports_unterminated_emphasis_and_code() {
+        let mut tokenizer = Tokenizer::new("**bold");
+        assert_eq!(
+            LatexConverter::convert_checked(tokenizer.tokenize()),
+            Err(ConvertError::UnterminatedEmphasis("**".to_string()))
+        );
+        let mut tokenizer = Tokenizer::new("```\ncode");
+        assert_eq!(
+            LatexConverter::convert_checked(tokenizer.tokenize()),
+            Err(ConvertError::UnterminatedCodeBlock("code".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_convert_checked_reports_unmappable_header_level() {
+        let mut tokenizer = Tokenizer::new("####### Too deep");
+        let result = LatexConverter::convert_checked(tokenizer.tokenize());
+        assert_eq!(result, Err(ConvertError::UnsupportedHeaderLevel(7)));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "header level 7 has no LaTeX equivalent"
+        );
+    }
+
+    #[test]
+    fn test_convert_checked_looks_inside_list_items() {
+        let mut tokenizer = Tokenizer::new("- see [link](...");
+        assert_eq!(
+            LatexConverter::convert_checked(tokenizer.tokenize()),
+            Err(ConvertError::UnterminatedLink("[link](...".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_document_loads_packages_for_nested_inline_tokens() {
+        let mut tokenizer = Tokenizer::new("- [docs](http://example.com)\n> ~~old~~");
+        let latex =
+            LatexConverter::convert_document(tokenizer.tokenize(), DocumentOptions::default());
+        assert!(latex.contains("\\usepackage{hyperref}\n"));
+        assert!(latex.contains("\\usepackage{ulem}\n"));
+    }
+
+    #[test]
+    fn test_convert_checked_accepts_well_formed_input() {
+        let mut tokenizer = Tokenizer::new("**bold** and [link](http://example.com)");
+        assert_eq!(
+            LatexConverter::convert_checked(tokenizer.tokenize()),
+            Ok("\\textbf{bold} and \\href{http://example.com}{link}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lenient_convert_keeps_unterminated_text() {
+        assert_eq!(convert("[a_b](x"), "[a\\_b](x");
+        assert_eq!(
+            convert("```\ncode"),
+            "\\begin{verbatim}\ncode\n\\end{verbatim}\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_all_special_characters() {
+        assert_eq!(
+            escape_latex("& % $ # _ { } ~ ^ \\"),
+            "\\& \\% \\$ \\# \\_ \\{ \\} \\textasciitilde{} \\textasciicircum{} \\textbackslash{}"
+        );
+    }
+
+    #[test]
+    fn test_escape_text_once() {
+        assert_eq!(convert("50% & rising"), "50\\% \\& rising");
+    }
+
+    #[test]
+    fn test_escape_inside_commands() {
+        assert_eq!(convert("**a_b**"), "\\textbf{a\\_b}");
+        assert_eq!(convert("*x^2*"), "\\textit{x\\textasciicircum{}2}");
+        assert_eq!(convert("## 100%"), "\\subsection{100\\%}\n");
+        assert_eq!(
+            convert("- {item}"),
+            "\\begin{itemize}\n\\item \\{item\\}\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_code_block_is_not_escaped() {
+        assert_eq!(
+            convert("```python\nx = {'a':  1}  # 50%\n```"),
+            "\\begin{lstlisting}[language=python]\nx = {'a':  1}  # 50%\n\\end{lstlisting}\n"
+        );
+        assert_eq!(
+            convert("```\na_b -c\n```"),
+            "\\begin{verbatim}\na_b -c\n\\end{verbatim}\n"
+        );
+    }
+
+    #[test]
+    fn test_adjacent_inline_elements_keep_their_spacing() {
+        assert_eq!(convert("**a** **b**"), "\\textbf{a} \\textbf{b}");
+        assert_eq!(
+            convert("**a**\n[b](http://x.io) `c`"),
+            "\\textbf{a} \\href{http://x.io}{b} \\texttt{c}"
+        );
+        assert_eq!(
+            convert("**bold***italic*"),
+            "\\textbf{bold}\\textit{italic}"
+        );
+    }
+
+    #[test]
+    fn test_bold_italic() {
+        assert_eq!(
+            convert("***strong emphasis***"),
+            "\\textbf{\\textit{strong emphasis}}"
+        );
+    }
+
+    #[test]
+    fn test_links_and_code_inside_emphasis() {
+        assert_eq!(
+            convert("**see [the docs](http://x.io) and `a_b`**"),
+            "\\textbf{see \\href{http://x.io}{the docs} and \\texttt{a\\_b}}"
+        );
+        assert_eq!(
+            convert("*math $x_1$ inside*"),
+            "\\textit{math $x_1$ inside}"
+        );
+    }
+
+    #[test]
+    fn test_nested_emphasis() {
+        assert_eq!(convert("**a *b* c**"), "\\textbf{a \\textit{b} c}");
+        assert_eq!(convert("*a **b** c*"), "\\textit{a \\textbf{b} c}");
+        assert_eq!(
+            convert("~~old **bold** text~~"),
+            "\\sout{old \\textbf{bold} text}"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_emphasis_falls_back_to_text() {
+        assert_eq!(convert("**bold*"), "**bold*");
+        assert_eq!(
+            convert("**bold** *italic*"),
+            "\\textbf{bold} \\textit{italic}"
+        );
+    }
+
+    #[test]
+    fn test_unterminated_bold_italic_is_literal() {
+        assert_eq!(convert("***x"), "***x");
+    }
+
+    #[test]
+    fn test_nested_lists_close_back_to_top_level() {
+        assert_eq!(
+            convert("- a\n  - b\n    1. c\n- d"),
+            "\\begin{itemize}\n\\item a\n\\begin{itemize}\n\\item b\n\\begin{enumerate}\n\\item c\n\\end{enumerate}\n\\end{itemize}\n\\item d\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_text_between_lists_makes_two_lists() {
+        let latex = convert("- a\n- b\n\ntext\n\n- c");
+        assert_eq!(latex.matches("\\begin{itemize}").count(), 2);
+        assert_eq!(latex.matches("\\end{itemize}").count(), 2);
+        assert!(latex.find("\\end{itemize}") < latex.find("text"));
+        assert!(latex.find("text") < latex.find("\\item c"));
+    }
+
+    #[test]
+    fn test_blank_line_between_items_keeps_one_list() {
+        assert_eq!(
+            convert("- a\n\n- b"),
+            "\\begin{itemize}\n\\item a\n\n\\item b\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_numbered_list_in_bullets() {
+        assert_eq!(
+            convert("1. one\n    - sub\n2. two"),
+            "\\begin{enumerate}\n\\item one\n\\begin{itemize}\n\\item sub\n\\end{itemize}\n\\item two\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_two_and_four_space_indents_nest_the_same() {
+        let expected = "\\begin{itemize}\n\\item top\n\\begin{itemize}\n\\item child\\end{itemize}\n\\end{itemize}\n";
+        assert_eq!(convert("- top\n  - child"), expected);
+        assert_eq!(convert("- top\n    - child"), expected);
+        assert_eq!(convert("* top\n\t* child"), expected);
+    }
+
+    #[test]
+    fn test_custom_list_environments() {
+        let config = ConverterConfig {
+            itemize_env: "compactitem".to_string(),
+            enumerate_env: "compactenum".to_string(),
+            ..ConverterConfig::default()
+        };
+        let mut tokenizer = Tokenizer::new("- a\n  1. b\n- c");
+        assert_eq!(
+            LatexConverter::convert_with(tokenizer.tokenize(), config),
+            "\\begin{compactitem}\n\\item a\n\\begin{compactenum}\n\\item b\n\\end{compactenum}\n\\item c\\end{compactitem}\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_digit_ordered_list() {
+        assert_eq!(
+            convert("10. ten\n11. eleven"),
+            "\\begin{enumerate}\n\\setcounter{enumi}{9}\n\\item ten\n\\item eleven\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_ordered_list_start_number() {
+        assert_eq!(
+            convert("3. three\n4. four"),
+            "\\begin{enumerate}\n\\setcounter{enumi}{2}\n\\item three\n\\item four\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_ordered_list_start_number() {
+        assert!(convert("1. one\n   5. five").contains("\\setcounter{enumii}{4}\n\\item five"));
+    }
+
+    #[test]
+    fn test_alphabetic_list_labels() {
+        assert_eq!(
+            convert("a. apples\nb. pears"),
+            "\\begin{enumerate}\n\\renewcommand{\\labelenumi}{\\alph{enumi}.}\n\\item apples\n\\item pears\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_roman_list_labels() {
+        assert_eq!(
+            convert("iii. three\niv. four"),
+            "\\begin{enumerate}\n\\renewcommand{\\labelenumi}{\\roman{enumi}.}\n\\setcounter{enumi}{2}\n\\item three\n\\item four\\end{enumerate}\n"
+        );
+        assert!(convert("1. one\n   I.  first")
+            .contains("\\renewcommand{\\labelenumii}{\\Roman{enumii}.}\n\\item first"));
+    }
+
+    #[test]
+    fn test_multi_line_blockquote() {
+        assert_eq!(
+            convert("> first line\n> second line"),
+            "\\begin{quote}\nfirst line\nsecond line\\end{quote}\n"
+        );
+    }
+
+    #[test]
+    fn test_nested_blockquote() {
+        assert_eq!(
+            convert("> a\n>> b\n> c"),
+            "\\begin{quote}\na\n\\begin{quote}\nb\n\\end{quote}\nc\\end{quote}\n"
+        );
+    }
+
+    #[test]
+    fn test_blockquote_followed_by_paragraph() {
+        assert_eq!(
+            convert("> quoted\nparagraph"),
+            "\\begin{quote}\nquoted\n\\end{quote}\nparagraph"
+        );
+    }
+
+    #[test]
+    fn test_image_keeps_path_extension() {
+        assert_eq!(
+            convert("![Plot of x_1](plots/x1.pdf)"),
+            "\\begin{figure}[h]\n\\centering\n\\includegraphics{plots/x1.pdf}\n\\caption{Plot of x\\_1}\n\\label{fig:plot-of-x1}\n\\end{figure}\n"
+        );
+    }
+
+    #[test]
+    fn test_image_without_alt_has_no_caption() {
+        assert_eq!(
+            convert("![](logo.png)"),
+            "\\begin{figure}[h]\n\\centering\n\\includegraphics{logo.png}\n\\end{figure}\n"
+        );
+    }
+
+    #[test]
+    fn test_figures_with_the_same_alt_get_unique_labels() {
+        let latex = convert("![My Plot](a.png)\n![my  plot](b.png)\n![My Plot](c.png)");
+        assert!(
+            latex.contains("\\includegraphics{a.png}\n\\caption{My Plot}\n\\label{fig:my-plot}\n")
+        );
+        assert!(latex
+            .contains("\\includegraphics{b.png}\n\\caption{my plot}\n\\label{fig:my-plot-2}\n"));
+        assert!(latex
+            .contains("\\includegraphics{c.png}\n\\caption{My Plot}\n\\label{fig:my-plot-3}\n"));
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Results: Q1 & Q2"), "results-q1-q2");
+        assert_eq!(slugify("  plain-text  "), "plain-text");
+    }
+
+    #[test]
+    fn test_footnotes() {
+        assert_eq!(
+            convert("Claim[^1] and a guess[^note].\n\n[^1]: See *the* [paper](http://x.io).\n[^NOTE]: Unsure."),
+            "Claim\\footnote{See \\textit{the} \\href{http://x.io}{paper}.} and a guess\\footnote{Unsure.}.\n\n"
+        );
+    }
+
+    #[test]
+    fn test_footnote_defined_before_use_and_unused_definition() {
+        assert_eq!(
+            convert("[^x]: Unused.\n[^y]: Used 50%.\n\nText[^y]."),
+            "Text\\footnote{Used 50\\%.}."
+        );
+    }
+
+    #[test]
+    fn test_undefined_footnote_is_literal() {
+        assert_eq!(convert("Text[^2]."), "Text[\\textasciicircum{}2].");
+    }
+
+    #[test]
+    fn test_link_is_not_an_image() {
+        assert_eq!(convert("[logo](logo.png)"), "\\href{logo.png}{logo}");
+        assert!(convert("![logo](logo.png)").contains("\\includegraphics{logo.png}"));
+    }
+
+    #[test]
+    fn test_strikethrough() {
+        assert_eq!(convert("a ~~b~~ ~c"), "a \\sout{b} \\textasciitilde{}c");
+    }
+
+    #[test]
+    fn test_strikethrough_between_text_spans() {
+        assert_eq!(
+            convert("before ~~gone~~ after"),
+            "before \\sout{gone} after"
+        );
+    }
+
+    #[test]
+    fn test_document_loads_ulem_for_strikethrough() {
+        let mut tokenizer = Tokenizer::new("~~old~~ new");
+        let latex =
+            LatexConverter::convert_document(tokenizer.tokenize(), DocumentOptions::default());
+        assert!(latex.contains("\\usepackage{ulem}\n"));
+    }
+
+    #[test]
+    fn test_code_backends() {
+        let input = "```rust\nlet x = 1;\n```";
+        for (code_backend, begin, end, package) in [
+            (CodeBackend::Verbatim, "verbatim}", "verbatim}", None),
+            (
+                CodeBackend::Listings,
+                "lstlisting}[language=rust]",
+                "lstlisting}",
+                Some("listings"),
+            ),
+            (
+                CodeBackend::Minted,
+                "minted}{rust}",
+                "minted}",
+                Some("minted"),
+            ),
+        ] {
+            let config = ConverterConfig {
+                code_backend,
+                ..ConverterConfig::default()
+            };
+            let latex = LatexConverter::convert_document_with(
+                Tokenizer::new(input).tokenize(),
+                DocumentOptions::default(),
+                config,
+            );
+            assert!(
+                latex.contains(&format!("\\begin{{{}\nlet x = 1;\n\\end{{{}\n", begin, end)),
+                "{:?}",
+                code_backend
+            );
+            for loaded in ["listings", "minted"] {
+                let usepackage = format!("\\usepackage{{{}}}", loaded);
+                assert_eq!(latex.contains(&usepackage), package == Some(loaded));
+            }
         }
     }
+
+    #[test]
+    fn test_code_span_is_escaped() {
+        assert_eq!(convert("`a_b & c`"), "\\texttt{a\\_b \\& c}");
+    }
+
+    #[test]
+    fn test_code_span_keeps_spaces_and_hyphens() {
+        assert_eq!(convert("`a  b`"), "\\texttt{a  b}");
+        assert_eq!(convert("`x - y`"), "\\texttt{x - y}");
+    }
+
+    #[test]
+    fn test_raw_latex_block_passes_through() {
+        let raw = "\\begin{myenv}[a=1]\n  $x$ & 100% ~ \\\\ _y_\n\\end{myenv}";
+        assert_eq!(
+            convert(&format!("Before\n\n```latex\n{}\n```\n\nAfter", raw)),
+            format!("Before\n\n{}\n\n\nAfter", raw)
+        );
+    }
+
+    #[test]
+    fn test_indented_code_block() {
+        assert_eq!(
+            convert("    a_b\n    -c"),
+            "\\begin{verbatim}\na_b\n-c\n\\end{verbatim}\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_code_block() {
+        assert_eq!(convert("```\n```"), "\\begin{verbatim}\n\\end{verbatim}\n");
+    }
+
+    #[test]
+    fn test_hyphens_are_not_rewritten() {
+        assert_eq!(convert("Run ls -la or rm -rf"), "Run ls -la or rm -rf");
+        assert_eq!(convert("5 - 3"), "5 - 3");
+        assert_eq!(convert("ls\n--all flag"), "ls --all flag");
+        assert_eq!(convert("-5 degrees"), "-5 degrees");
+        assert_eq!(
+            convert("- use --verbose -q"),
+            "\\begin{itemize}\n\\item use --verbose -q\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_list_item_hyphens_stay_literal() {
+        assert_eq!(
+            convert("- a - b"),
+            "\\begin{itemize}\n\\item a - b\\end{itemize}\n"
+        );
+        assert_eq!(
+            convert("-    a - b\n1.   c - d"),
+            "\\begin{itemize}\n\\item a - b\n\\end{itemize}\n\\begin{enumerate}\n\\item c - d\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_smart_dashes() {
+        let config = ConverterConfig {
+            typography: TypographyOptions {
+                smart_dashes: true,
+                ..TypographyOptions::default()
+            },
+            ..ConverterConfig::default()
+        };
+        let mut tokenizer = Tokenizer::new("1990 - 2000, ls -la, **a - b** and `x - y`");
+        assert_eq!(
+            LatexConverter::convert_with(tokenizer.tokenize(), config),
+            "1990 -- 2000, ls -la, \\textbf{a -- b} and \\texttt{x - y}"
+        );
+    }
+
+    #[test]
+    fn test_smart_quotes() {
+        let config = || ConverterConfig {
+            typography: TypographyOptions {
+                smart_quotes: true,
+                ..TypographyOptions::default()
+            },
+            ..ConverterConfig::default()
+        };
+        let convert_smart = |input| {
+            let mut tokenizer = Tokenizer::new(input);
+            LatexConverter::convert_with(tokenizer.tokenize(), config())
+        };
+        assert_eq!(
+            convert_smart("She said \"don't\" and 'maybe'."),
+            "She said ``don't'' and `maybe'."
+        );
+        assert_eq!(
+            convert_smart("\"*Quoted* emphasis\"\n\"next line\""),
+            "``\\textit{Quoted} emphasis'' ``next line''"
+        );
+        assert_eq!(convert("\"plain\""), "\"plain\"");
+    }
+
+    #[test]
+    fn test_smart_quotes_leave_code_and_urls_alone() {
+        let config = ConverterConfig {
+            typography: TypographyOptions {
+                smart_quotes: true,
+                ..TypographyOptions::default()
+            },
+            ..ConverterConfig::default()
+        };
+        let mut tokenizer =
+            Tokenizer::new("\"x\" `\"y\"` [it's](http://x.io/'a') <http://x.io/\"b\">");
+        assert_eq!(
+            LatexConverter::convert_with(tokenizer.tokenize(), config),
+            "``x'' \\texttt{\"y\"} \\href{http://x.io/'a'}{it's} \\url{http://x.io/\"b\"}"
+        );
+    }
+
+    #[test]
+    fn test_html_comments_become_latex_comments() {
+        assert_eq!(
+            convert("before <!-- a note --> after"),
+            "before % a note\n after"
+        );
+        assert_eq!(
+            convert("para\n<!-- two\nlines -->\nmore"),
+            "para % two\n% lines\n more"
+        );
+    }
+
+    #[test]
+    fn test_dropped_comments() {
+        let config = ConverterConfig {
+            drop_comments: true,
+            ..ConverterConfig::default()
+        };
+        let mut tokenizer = Tokenizer::new("before <!-- a note --> after\n<!-- gone -->\nend");
+        assert_eq!(
+            LatexConverter::convert_with(tokenizer.tokenize(), config),
+            "before  after end"
+        );
+    }
+
+    #[test]
+    fn test_comment_between_paragraphs_leaves_no_stray_text() {
+        let input = "First.\n\n<!-- note -->\n\nSecond. <!-- never closed\n\nstill a comment";
+        assert_eq!(
+            convert(input),
+            "First.\n\n% note\n\n\nSecond. % never closed\n%\n% still a comment\n"
+        );
+        let config = ConverterConfig {
+            drop_comments: true,
+            ..ConverterConfig::default()
+        };
+        let latex = LatexConverter::convert_with(Tokenizer::new(input).tokenize(), config);
+        assert!(!latex.contains(['<', '>', '%']));
+        assert!(!latex.contains("note") && !latex.contains("still"));
+        assert!(latex.starts_with("First.\n\n") && latex.contains("Second."));
+    }
+
+    #[test]
+    fn test_percent_signs_never_start_a_comment() {
+        assert_eq!(convert("100% done"), "100\\% done");
+        let latex =
+            convert("# 50% off\n- 10% item\n**5%** *6%* [7%](http://x.io/a%20b) ![8% alt](9%.png)");
+        assert_eq!(latex.matches('%').count(), latex.matches("\\%").count());
+    }
+
+    #[test]
+    fn test_center_div() {
+        assert_eq!(
+            convert("::: center\nA *centered* line\n:::\n\nAfter"),
+            "\\begin{center}\nA \\textit{centered} line\n\\end{center}\n\n\nAfter"
+        );
+        assert_eq!(
+            convert("::: right\n- a\n:::"),
+            "\\begin{flushright}\n\\begin{itemize}\n\\item a\\end{itemize}\n\\end{flushright}\n"
+        );
+        assert_eq!(convert("::: note\nkept\n:::"), "kept");
+    }
+
+    #[test]
+    fn test_hard_break() {
+        assert_eq!(convert("line one  \nline two"), "line one\\\\\nline two");
+    }
+
+    #[test]
+    fn test_definition_list() {
+        assert_eq!(
+            convert("Rust\n: A language\n: An oxide\n\nCargo\n: The build_tool"),
+            "\\begin{description}\n\\item[Rust] A language\n\\item An oxide\n\n\\item[Cargo] The build\\_tool\\end{description}\n"
+        );
+    }
+
+    #[test]
+    fn test_definition_list_parses_inline_markup() {
+        assert_eq!(
+            convert("**Term**\n: *def* with `code`"),
+            "\\begin{description}\n\\item[\\textbf{Term}] \\textit{def} with \\texttt{code}\\end{description}\n"
+        );
+    }
+
+    #[test]
+    fn test_single_definition() {
+        assert_eq!(
+            convert("Term\n: Meaning"),
+            "\\begin{description}\n\\item[Term] Meaning\\end{description}\n"
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            convert("| Name | Share |\n|:-:|--:|\n| A_1 | 50% |\n| B | 50% |"),
+            "\\begin{tabular}{cr}\n\\hline\nName & Share \\\\\n\\hline\nA\\_1 & 50\\% \\\\\nB & 50\\% \\\\\n\\hline\n\\end{tabular}\n"
+        );
+    }
+
+    #[test]
+    fn test_two_by_two_table() {
+        assert_eq!(
+            convert("| a | b |\n|---|---|\n| 1 | 2 |\n| 3 |"),
+            "\\begin{tabular}{ll}\n\\hline\na & b \\\\\n\\hline\n1 & 2 \\\\\n3 &  \\\\\n\\hline\n\\end{tabular}\n"
+        );
+    }
+
+    #[test]
+    fn test_horizontal_rule() {
+        assert_eq!(
+            convert("a\n***\nb"),
+            "a\n\\noindent\\rule{\\linewidth}{0.4pt}\n\nb"
+        );
+    }
+
+    #[test]
+    fn test_underscore_emphasis() {
+        assert_eq!(
+            convert("_x_ __y__ foo_bar_baz"),
+            "\\textit{x} \\textbf{y} foo\\_bar\\_baz"
+        );
+    }
+
+    #[test]
+    fn test_brackets_without_url_keep_following_text() {
+        assert_eq!(convert("[x] (y) **z**"), "[x] (y) \\textbf{z}");
+    }
+
+    #[test]
+    fn test_reference_link() {
+        assert_eq!(
+            convert("Read [the guide][g].\n\n[g]: http://example.com/guide"),
+            "Read \\href{http://example.com/guide}{the guide}.\n\n"
+        );
+    }
+
+    #[test]
+    fn test_undefined_reference_is_literal() {
+        assert_eq!(convert("See [x][nope] now"), "See [x][nope] now");
+    }
+
+    #[test]
+    fn test_link_url_with_special_characters() {
+        assert_eq!(
+            convert("[my_page](https://example.com/path_with_underscore#frag)"),
+            "\\href{https://example.com/path_with_underscore\\#frag}{my\\_page}"
+        );
+        assert_eq!(
+            convert("[q](https://example.com/?a=1&b=50%)"),
+            "\\href{https://example.com/?a=1&b=50\\%}{q}"
+        );
+    }
+
+    #[test]
+    fn test_link_title_stays_out_of_the_url() {
+        assert_eq!(
+            convert("see [docs](http://x.io \"The docs\") now"),
+            "see \\href{http://x.io}{docs} now"
+        );
+        assert_eq!(
+            convert("# A [link](http://x.io \"Title\") here"),
+            "\\section{A \\href{http://x.io}{link} here}\n"
+        );
+    }
+
+    #[test]
+    fn test_link_styles() {
+        let render = |link_style| {
+            let config = ConverterConfig {
+                link_style,
+                ..ConverterConfig::default()
+            };
+            let mut tokenizer = Tokenizer::new("[the docs](http://x.io/a#b) or http://y.io");
+            LatexConverter::convert_with(tokenizer.tokenize(), config)
+        };
+        assert_eq!(
+            render(LinkStyle::Href),
+            "\\href{http://x.io/a\\#b}{the docs} or \\url{http://y.io}"
+        );
+        assert_eq!(
+            render(LinkStyle::Footnote),
+            "the docs\\footnote{\\url{http://x.io/a\\#b}} or \\url{http://y.io}"
+        );
+        assert_eq!(
+            render(LinkStyle::UrlOnly),
+            "\\url{http://x.io/a\\#b} or \\url{http://y.io}"
+        );
+    }
+
+    #[test]
+    fn test_escape_url_leaves_url_syntax_alone() {
+        assert_eq!(
+            escape_url("http://a.io/x_y?p=1&q=%20#top"),
+            "http://a.io/x_y?p=1&q=\\%20\\#top"
+        );
+    }
+
+    #[test]
+    fn test_bare_url_in_sentence() {
+        assert_eq!(
+            convert("Visit https://example.com/50%_off#top, then <https://x.org/~a>."),
+            "Visit \\url{https://example.com/50\\%_off\\#top}, then \\url{https://x.org/~a}."
+        );
+    }
+
+    #[test]
+    fn test_inline_formatting_in_headers() {
+        assert_eq!(convert("## A *word*"), "\\subsection{A \\textit{word}}\n");
+        assert_eq!(
+            convert("# Use `cargo` & **go**"),
+            "\\section{Use \\texttt{cargo} \\& \\textbf{go}}\n"
+        );
+    }
+
+    #[test]
+    fn test_task_list() {
+        assert_eq!(
+            convert("- [ ] todo\n- [x] done\n- plain"),
+            "\\begin{itemize}\n\\item[$\\square$] todo\n\\item[$\\boxtimes$] done\n\\item plain\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_document_loads_amssymb_for_task_lists() {
+        let mut tokenizer = Tokenizer::new("- [x] done");
+        let latex =
+            LatexConverter::convert_document(tokenizer.tokenize(), DocumentOptions::default());
+        assert!(latex.contains("\\usepackage{amssymb}\n"));
+    }
+
+    #[test]
+    fn test_bold_list_item() {
+        assert_eq!(
+            convert("- **bold**"),
+            "\\begin{itemize}\n\\item \\textbf{bold}\\end{itemize}\n"
+        );
+        assert_eq!(
+            convert("1. run `make`\n  - see [docs](http://d.io)"),
+            "\\begin{enumerate}\n\\item run \\texttt{make}\n\\begin{itemize}\n\\item see \\href{http://d.io}{docs}\\end{itemize}\n\\end{enumerate}\n"
+        );
+    }
+
+    #[test]
+    fn test_inline_formatting_in_list_items_and_quotes() {
+        assert_eq!(
+            convert("- some **bold** text\n- [a](http://b.c)"),
+            "\\begin{itemize}\n\\item some \\textbf{bold} text\n\\item \\href{http://b.c}{a}\\end{itemize}\n"
+        );
+        assert_eq!(
+            convert("> quoted *word*"),
+            "\\begin{quote}\nquoted \\textit{word}\\end{quote}\n"
+        );
+    }
+
+    #[test]
+    fn test_hard_break_and_paragraph_break_differ() {
+        assert_eq!(convert("one  \ntwo\\\nthree"), "one\\\\\ntwo\\\\\nthree");
+        assert_eq!(convert("one\n\n\ntwo"), "one\n\ntwo");
+    }
+
+    #[test]
+    fn test_paragraphs_are_separated_by_a_blank_line() {
+        assert_eq!(
+            convert("First line\nstill *first*.\n\nSecond paragraph."),
+            "First line still \\textit{first}.\n\nSecond paragraph."
+        );
+    }
+
+    #[test]
+    fn test_soft_wrapped_lines_share_a_latex_line() {
+        assert_eq!(
+            convert("a line with \ntrailing space\nand `code`\n[a link](http://x.io)"),
+            "a line with trailing space and \\texttt{code} \\href{http://x.io}{a link}"
+        );
+    }
+
+    #[test]
+    fn test_newline_runs_become_paragraph_breaks() {
+        let text = |text: &str| Token::Text(text.to_string());
+        let one = vec![text("a"), Token::Newline, text("b")];
+        assert_eq!(LatexConverter::convert(one), "a b");
+        let two = vec![text("a"), Token::Newline, Token::Newline, text("b")];
+        assert_eq!(LatexConverter::convert(two), "a\n\nb");
+        let three = vec![
+            text("a"),
+            Token::Newline,
+            Token::Newline,
+            Token::Newline,
+            text("b"),
+        ];
+        assert_eq!(LatexConverter::convert(three), "a\n\nb");
+    }
+
+    #[test]
+    fn test_newline_after_block_is_kept() {
+        assert_eq!(
+            convert("# Title\ntext\n- item"),
+            "\\section{Title}\n\ntext\n\\begin{itemize}\n\\item item\\end{itemize}\n"
+        );
+    }
+
+    #[test]
+    fn test_paragraph_break_closes_quote() {
+        assert_eq!(convert("> a\n\nb"), "\\begin{quote}\na\\end{quote}\n\n\nb");
+    }
+
+    #[test]
+    fn test_angle_bracket_autolink() {
+        assert_eq!(
+            convert("<https://example.com>"),
+            "\\url{https://example.com}"
+        );
+    }
+
+    #[test]
+    fn test_angle_brackets_outside_autolinks_are_escaped() {
+        assert_eq!(
+            convert("a < b and <not a link> and c > d"),
+            "a \\textless{} b and \\textless{}not a link\\textgreater{} and c \\textgreater{} d"
+        );
+    }
+
+    #[test]
+    fn test_math_passes_through() {
+        assert_eq!(convert("Area $\\pi r^2$."), "Area $\\pi r^2$.");
+        assert_eq!(convert("$$a_1 + b_1$$"), "\\[a_1 + b_1\\]");
+    }
+
+    #[test]
+    fn test_stray_dollar_is_escaped() {
+        assert_eq!(convert("It costs $5."), "It costs \\$5.");
+        assert_eq!(convert("$ x $"), "\\$ x \\$");
+    }
+
+    #[test]
+    fn test_escaped_markdown_characters_are_literal() {
+        assert_eq!(convert("\\*not italic\\*"), "*not italic*");
+        assert_eq!(convert("\\# not a header"), "\\# not a header");
+        assert_eq!(convert("a \\\\ b"), "a \\textbackslash{} b");
+        assert_eq!(convert("*5 \\* 3 = 15*"), "\\textit{5 * 3 = 15}");
+        assert_eq!(convert("**a \\** b**"), "\\textbf{a ** b}");
+        assert_eq!(convert("**bold\\***"), "\\textbf{bold*}");
+    }
+
+    #[test]
+    fn test_link_url_is_not_escaped() {
+        assert_eq!(
+            convert("[a_b](http://example.com/a_b)"),
+            "\\href{http://example.com/a_b}{a\\_b}"
+        );
+    }
 }
@@ -0,0 +1,46 @@
+pub mod error;
+pub mod file_utils;
+pub mod latex_converter;
+pub mod markdown;
+pub mod renderer;
+pub mod tokenizer;
+
+pub use error::ConvertError;
+pub use latex_converter::{
+    CodeBackend, ConverterConfig, DocumentOptions, LatexConverter, LinkStyle, TypographyOptions,
+};
+pub use markdown::to_markdown;
+pub use renderer::Renderer;
+pub use tokenizer::{Numbering, Span, Spanned, Token, Tokenizer};
+
+/// Splits a Markdown string into tokens. Use a `Tokenizer` directly to
+/// read the tokens one at a time instead of collecting them.
+///
+/// ```
+/// use markdown_to_latex::{tokenize, Token};
+///
+/// assert_eq!(
+///     tokenize("*hi*"),
+///     vec![Token::Italic(vec![Token::Text("hi".to_string())])]
+/// );
+/// ```
+pub fn tokenize(input: &str) -> Vec<Token> {
+    Tokenizer::new(input).tokenize()
+}
+
+/// Pretty-prints the tokens of a Markdown string, for seeing how the
+/// input was read when the LaTeX output looks wrong.
+pub fn tokens_debug_string(input: &str) -> String {
+    format!("{:#?}", tokenize(input))
+}
+
+/// Converts a Markdown string into the standalone LaTeX document the
+/// command-line tool writes, without touching the filesystem.
+pub fn convert_str(markdown: &str) -> String {
+    LatexConverter::convert_document(tokenize(markdown), DocumentOptions::default())
+}
+
+/// Converts a Markdown string into a LaTeX fragment without a preamble.
+pub fn markdown_to_latex(input: &str) -> String {
+    LatexConverter::convert(tokenize(input))
+}
@@ -1,28 +1,126 @@
-mod file_utils;
-mod latex_converter;
-mod tokenizer;
+use std::env;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process;
 
-use file_utils::{read_file_to_string, write_to_file};
-use latex_converter::LatexConverter;
-use tokenizer::Tokenizer;
+use markdown_to_latex::file_utils::{
+    create_file_writer, read_file_to_string, read_stdin_to_string,
+};
+use markdown_to_latex::{
+    tokens_debug_string, ConverterConfig, DocumentOptions, LatexConverter, Tokenizer,
+};
+
+const DEFAULT_INPUT: &str = "data/example.md";
+const DEFAULT_OUTPUT: &str = "data/output.tex";
+
+fn usage() {
+    eprintln!("Usage: markdown_to_latex [options] [input.md|-] [output.tex|-]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --starred-sections  Emit unnumbered \\section* style headers");
+    eprintln!("  --smart-quotes      Turn straight quotes into ``curly'' LaTeX quotes");
+    eprintln!("  --tokens            Print the token stream to stderr before converting");
+    eprintln!();
+    eprintln!("Reads stdin when the input is `-` or omitted with piped input.");
+    eprintln!("Writes next to the input with a .tex extension when no output is given,");
+    eprintln!("or to stdout when the output is `-` or the input is stdin.");
+}
 
 fn main() {
-    let file_path = "data/example.md";
+    let (flags, args): (Vec<String>, Vec<String>) = env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with('-') && arg != "-");
+    let mut config = ConverterConfig::default();
+    let mut print_tokens = false;
+    for flag in &flags {
+        match flag.as_str() {
+            "-h" | "--help" => {
+                usage();
+                return;
+            }
+            "--starred-sections" => config.starred_sections = true,
+            "--smart-quotes" => config.typography.smart_quotes = true,
+            "--tokens" => print_tokens = true,
+            _ => {
+                eprintln!("Unknown option: {}", flag);
+                usage();
+                process::exit(2);
+            }
+        }
+    }
+    if args.len() > 2 {
+        usage();
+        process::exit(2);
+    }
+    // Without arguments or piped input, fall back to the bundled example.
+    let (input, output) = if args.is_empty() && io::stdin().is_terminal() {
+        (Some(DEFAULT_INPUT), Some(DEFAULT_OUTPUT.to_string()))
+    } else {
+        let input = args.first().map(String::as_str);
+        let output = match (input, args.get(1)) {
+            (_, Some(output)) => Some(output.clone()),
+            (None | Some("-"), None) => None,
+            (Some(input), None) => Some(
+                Path::new(input)
+                    .with_extension("tex")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        };
+        (input, output)
+    };
 
-    let content = match read_file_to_string(file_path) {
+    if let Some(path) = input.filter(|&path| path != "-") {
+        if !Path::new(path).is_file() {
+            eprintln!("Input file not found: {}", path);
+            usage();
+            process::exit(2);
+        }
+    }
+
+    let content = match input {
+        None | Some("-") => read_stdin_to_string(),
+        Some(path) => read_file_to_string(path),
+    };
+    let content = match content {
         Ok(content) => content,
         Err(e) => {
             eprintln!("Error reading file: {}", e);
-            return;
+            process::exit(1);
         }
     };
 
-    let mut tokenizer = Tokenizer::new(&content);
-    let tokens = tokenizer.tokenize();
+    if print_tokens {
+        eprintln!("{}", tokens_debug_string(&content));
+    }
 
-    let latex_content = LatexConverter::convert(tokens);
-    match write_to_file(latex_content, "data/output.tex") {
-        Ok(_) => println!("Tex was saved"),
-        Err(e) => println!("Error: {}", e),
+    // Tokens are converted as they are read and written straight out, so
+    // the full LaTeX output is never held in memory.
+    let tokens = Tokenizer::new(&content);
+    let options = DocumentOptions::default();
+    match output.as_deref() {
+        None | Some("-") => {
+            let mut writer = BufWriter::new(io::stdout().lock());
+            let result =
+                LatexConverter::convert_document_to_writer(tokens, options, config, &mut writer)
+                    .and_then(|()| writer.flush());
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Some(path) => {
+            let result = create_file_writer(path).and_then(|mut writer| {
+                LatexConverter::convert_document_to_writer(tokens, options, config, &mut writer)?;
+                writer.flush()
+            });
+            match result {
+                Ok(()) => println!("Tex was saved"),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,320 @@
+use crate::tokenizer::{to_roman, Align, Construct, Numbering, Token};
+
+impl Token {
+    /// Writes this token back out as Markdown, like `to_markdown` does for
+    /// a whole stream.
+    pub fn to_markdown(&self) -> String {
+        to_markdown(std::slice::from_ref(self))
+    }
+}
+
+/// Writes a token stream back out as Markdown that tokenizes to the same
+/// tokens again. The source is normalized rather than reproduced: emphasis
+/// uses stars, bullets use `-`, links are inline and footnotes are
+/// numbered, with their definitions at the top of the document.
+pub fn to_markdown(tokens: &[Token]) -> String {
+    let (front_matter, body): (Vec<&Token>, Vec<&Token>) = tokens
+        .iter()
+        .partition(|token| matches!(token, Token::FrontMatter(_)));
+    let mut writer = MarkdownWriter::default();
+    let front_matter = writer.blocks(front_matter);
+    let body = writer.blocks(body);
+    let notes: String = writer
+        .notes
+        .iter()
+        .enumerate()
+        .map(|(index, note)| format!("[^{}]: {}\n", index + 1, note))
+        .collect();
+    front_matter + &notes + &body
+}
+
+#[derive(Default)]
+struct MarkdownWriter {
+    notes: Vec<String>, // footnote text, in order of reference
+}
+
+impl MarkdownWriter {
+    fn write(&mut self, token: &Token, line_start: bool) -> String {
+        match token {
+            Token::Header(children, level) => {
+                format!(
+                    "{} {}",
+                    "#".repeat(usize::from(*level)),
+                    self.inline(children)
+                )
+            }
+            Token::Bold(children) => format!("**{}**", self.inline(children)),
+            Token::Italic(children) => format!("*{}*", self.inline(children)),
+            Token::BoldItalic(children) => format!("***{}***", self.inline(children)),
+            Token::Strikethrough(children) => format!("~~{}~~", self.inline(children)),
+            Token::Link(text, url, None) => format!("[{}]({})", text, url),
+            Token::Link(text, url, Some(title)) => format!("[{}]({} \"{}\")", text, url, title),
+            Token::Footnote(children) => {
+                let note = self.inline(children);
+                self.notes.push(note);
+                format!("[^{}]", self.notes.len())
+            }
+            Token::Image(alt, path) => format!("![{}]({})", alt, path),
+            Token::ListItem(children, number, indent) => {
+                // An uppercase marker needs two spaces to be read as one.
+                let marker = match number {
+                    Some((number, numbering @ (Numbering::UpperAlpha | Numbering::UpperRoman))) => {
+                        format!("{}. ", Self::ordinal(*number, *numbering))
+                    }
+                    Some((number, numbering)) => format!("{}.", Self::ordinal(*number, *numbering)),
+                    None => "-".to_string(),
+                };
+                format!(
+                    "{}{} {}",
+                    " ".repeat(*indent),
+                    marker,
+                    self.inline(children)
+                )
+            }
+            Token::TaskItem(children, checked, indent) => {
+                let mark = if *checked { 'x' } else { ' ' };
+                format!(
+                    "{}- [{}] {}",
+                    " ".repeat(*indent),
+                    mark,
+                    self.inline(children)
+                )
+            }
+            Token::BlockQuote(children, depth) => {
+                format!("{} {}", ">".repeat(*depth), self.inline(children))
+            }
+            Token::Code(code) => Self::code_span(code),
+            Token::InlineMath(formula) => format!("${}$", formula),
+            Token::DisplayMath(formula) => format!("$${}$$", formula),
+            Token::CodeBlock(code, language) => {
+                Self::code_block(code, language.as_deref().unwrap_or(""))
+            }
+            Token::RawLatex(latex) => Self::code_block(latex, "latex"),
+            Token::Text(text) => escape_markdown(text, line_start),
+            Token::Table {
+                headers,
+                rows,
+                alignments,
+            } => {
+                let delimiters: Vec<String> = alignments
+                    .iter()
+                    .map(|alignment| match alignment {
+                        Align::Left => "---",
+                        Align::Center => ":-:",
+                        Align::Right => "--:",
+                    })
+                    .map(String::from)
+                    .collect();
+                let lines: Vec<String> = [headers, &delimiters]
+                    .into_iter()
+                    .chain(rows)
+                    .map(|cells| format!("| {} |", cells.join(" | ")))
+                    .collect();
+                lines.join("\n")
+            }
+            Token::DefinitionItem { term, definitions } => {
+                let mut markdown = self.blocks(term);
+                for definition in definitions {
+                    markdown.push_str("\n: ");
+                    markdown.push_str(&self.inline(definition));
+                }
+                markdown
+            }
+            Token::HorizontalRule => "***".to_string(),
+            Token::HardBreak => "\\\n".to_string(),
+            Token::ParagraphBreak => "\n\n".to_string(),
+            Token::Newline => "\n".to_string(),
+            Token::Unterminated(Construct::CodeBlock(language), text) => {
+                format!("```{}\n{}", language.as_deref().unwrap_or(""), text)
+            }
+            // A stray marker is escaped so that it cannot pair up with
+            // the normalized markers written around it.
+            Token::Unterminated(_, text) => escape_markdown(text, line_start),
+            Token::Comment(text) => format!("<!-- {} -->", text),
+            Token::FrontMatter(fields) => Self::front_matter(fields),
+            Token::Div(class, children) => format!("::: {}\n{}\n:::", class, self.blocks(children)),
+        }
+    }
+
+    fn blocks<'t>(&mut self, tokens: impl IntoIterator<Item = &'t Token>) -> String {
+        let mut markdown = String::new();
+        for token in tokens {
+            let line_start = markdown.is_empty() || markdown.ends_with('\n');
+            let written = self.write(token, line_start);
+            markdown.push_str(&written);
+        }
+        markdown
+    }
+
+    fn inline(&mut self, tokens: &[Token]) -> String {
+        tokens
+            .iter()
+            .map(|token| self.write(token, false))
+            .collect()
+    }
+
+    fn front_matter(fields: &[(String, String)]) -> String {
+        let mut markdown = "---\n".to_string();
+        for (key, value) in fields {
+            markdown.push_str(&format!("{}: {}\n", key, value));
+        }
+        markdown + "---\n"
+    }
+
+    /// Fences the code with one backtick more than its longest run, padding
+    /// it with spaces where the tokenizer would otherwise strip or misread
+    /// its ends. A fence of three or more at the start of a line is not read
+    /// as a code block, since the closing fence puts a backtick after it.
+    fn code_span(code: &str) -> String {
+        let longest = code.split(|ch| ch != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat(longest + 1);
+        let padded = code.starts_with('`')
+            || code.ends_with('`')
+            || (code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty());
+        if padded {
+            format!("{} {} {}", fence, code, fence)
+        } else {
+            format!("{}{}{}", fence, code, fence)
+        }
+    }
+
+    /// Fences the code with more backticks than any fence line inside it.
+    fn code_block(code: &str, language: &str) -> String {
+        let longest = code
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && line.chars().all(|ch| ch == '`'))
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest.max(2) + 1);
+        if code.is_empty() {
+            format!("{}{}\n{}", fence, language, fence)
+        } else {
+            format!("{}{}\n{}\n{}", fence, language, code, fence)
+        }
+    }
+
+    fn ordinal(number: u32, numbering: Numbering) -> String {
+        let letter = |first: u8| {
+            char::from_u32(u32::from(first) + number.saturating_sub(1))
+                .map(String::from)
+                .unwrap_or_default()
+        };
+        match numbering {
+            Numbering::Decimal => number.to_string(),
+            Numbering::LowerAlpha => letter(b'a'),
+            Numbering::UpperAlpha => letter(b'A'),
+            Numbering::LowerRoman => to_roman(number),
+            Numbering::UpperRoman => to_roman(number).to_uppercase(),
+        }
+    }
+}
+
+/// Backslash-escapes the characters that would start markup, and at the
+/// start of a line those that would start a block.
+fn escape_markdown(text: &str, line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (index, ch) in text.char_indices() {
+        let starts_block = line_start && index == 0 && matches!(ch, '-' | '+' | ':' | '=');
+        let ends_marker = line_start
+            && matches!(ch, '.' | ')')
+            && index > 0
+            && text[..index].chars().all(|ch| ch.is_ascii_alphanumeric());
+        if starts_block
+            || ends_marker
+            || matches!(
+                ch,
+                '\\' | '*' | '_' | '~' | '$' | '[' | ']' | '<' | '>' | '#' | '|' | '!' | '`'
+            )
+        {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+
+    /// Reads unclosed constructs as the text they are rendered as, since a
+    /// re-emitted stray marker is escaped and comes back as plain text.
+    fn normalize(tokens: Vec<Token>) -> Vec<Token> {
+        let mut normalized: Vec<Token> = Vec::new();
+        for token in tokens {
+            let token = match token {
+                Token::Unterminated(construct, text)
+                    if !matches!(construct, Construct::CodeBlock(_)) =>
+                {
+                    Token::Text(text)
+                }
+                Token::Bold(children) => Token::Bold(normalize(children)),
+                Token::Italic(children) => Token::Italic(normalize(children)),
+                Token::BoldItalic(children) => Token::BoldItalic(normalize(children)),
+                Token::Strikethrough(children) => Token::Strikethrough(normalize(children)),
+                Token::Footnote(children) => Token::Footnote(normalize(children)),
+                Token::Header(children, level) => Token::Header(normalize(children), level),
+                Token::ListItem(children, number, indent) => {
+                    Token::ListItem(normalize(children), number, indent)
+                }
+                Token::BlockQuote(children, depth) => Token::BlockQuote(normalize(children), depth),
+                token => token,
+            };
+            match (normalized.last_mut(), token) {
+                (Some(Token::Text(previous)), Token::Text(text)) => previous.push_str(&text),
+                (_, token) => normalized.push(token),
+            }
+        }
+        normalized
+    }
+
+    fn round_trip(input: &str) {
+        let tokens = Tokenizer::new(input).tokenize();
+        let markdown = to_markdown(&tokens);
+        assert_eq!(
+            normalize(Tokenizer::new(&markdown).tokenize()),
+            normalize(tokens),
+            "re-emitted as:\n{}",
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(
+            "---\ntitle: Notes\n---\n# Notes on *rust*\n\nSome **bold *and* italic**, ~~old~~ text\n\
+             with `a``b`, $x^2$ and [a link](http://x.io \"Home\").[^n]\n\n\
+             - one\n  1. nested\n  2. two\n- [x] done\n\n> quoted\n\n\
+             | a | b |\n|:-:|--:|\n| 1 | 2 |\n\n```rust\nfn main() {}\n```\n\n\
+             Term\n: Meaning\n\n::: center\n- *a*\n:::\n\n***\n\nEscaped \\* star, 1\\. not a list <!-- note -->\n\n\
+             [^n]: A *footnote*.",
+        );
+        round_trip("I said *hi and _bye_ today\n\n- a [broken link\n\n``a``b``` and\n\n``` `` ```");
+    }
+
+    #[test]
+    fn test_definition_list_round_trip() {
+        round_trip("snake_case\n: def with `code`\n: *two*\n\n**Bold** term\n: a_b");
+    }
+
+    #[test]
+    fn test_text_is_escaped() {
+        let tokens = vec![Token::Text("- 3. *not* [markup] #1".to_string())];
+        assert_eq!(to_markdown(&tokens), "\\- 3. \\*not\\* \\[markup\\] \\#1");
+        assert_eq!(Tokenizer::new(&to_markdown(&tokens)).tokenize(), tokens);
+    }
+
+    #[test]
+    fn test_single_token() {
+        let token = Token::ListItem(
+            vec![Token::Bold(vec![Token::Text("b".to_string())])],
+            Some((3, Numbering::UpperAlpha)),
+            2,
+        );
+        assert_eq!(token.to_markdown(), "  C.  **b**");
+        round_trip("A.  first\nB.  second\n  iv. nested");
+    }
+}
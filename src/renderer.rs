@@ -0,0 +1,405 @@
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::io::{self, Write};
+
+use crate::tokenizer::{Align, Construct, Numbering, Token};
+
+/// An output format that a token stream can be rendered into. Each method
+/// appends its output to `out`.
+///
+/// Arguments named `content` have already been rendered; everything else is
+/// raw Markdown source that the renderer escapes as its format requires.
+pub trait Renderer {
+    fn render_text(&mut self, out: &mut String, text: &str);
+    fn render_header(&mut self, out: &mut String, content: &str, level: u8);
+    fn render_bold(&mut self, out: &mut String, content: &str);
+    fn render_italic(&mut self, out: &mut String, content: &str);
+    fn render_bold_italic(&mut self, out: &mut String, content: &str);
+    fn render_strikethrough(&mut self, out: &mut String, content: &str);
+    fn render_link(&mut self, out: &mut String, content: &str, url: &str, title: Option<&str>);
+    /// A link whose text is the URL itself.
+    fn render_url(&mut self, out: &mut String, url: &str);
+    fn render_image(&mut self, out: &mut String, alt: &str, path: &str);
+    fn render_footnote(&mut self, out: &mut String, content: &str);
+    fn render_list_item(
+        &mut self,
+        out: &mut String,
+        content: &str,
+        number: Option<(u32, Numbering)>,
+        indent: usize,
+    );
+    fn render_task_item(&mut self, out: &mut String, content: &str, checked: bool, indent: usize);
+    fn render_blockquote(&mut self, out: &mut String, content: &str, depth: usize);
+    fn render_code(&mut self, out: &mut String, code: &str);
+    fn render_code_block(&mut self, out: &mut String, code: &str, language: Option<&str>);
+    /// Source written in the output format itself, to be emitted unchanged.
+    fn render_raw_latex(&mut self, out: &mut String, latex: &str);
+    fn render_inline_math(&mut self, out: &mut String, formula: &str);
+    fn render_display_math(&mut self, out: &mut String, formula: &str);
+    fn render_table(
+        &mut self,
+        out: &mut String,
+        headers: &[String],
+        rows: &[Vec<String>],
+        alignments: &[Align],
+    );
+    /// A rendered term with one or more rendered definitions; consecutive
+    /// items belong to one list, closed by `end_lists` like the other lists.
+    fn render_definition_item(&mut self, out: &mut String, term: &str, definitions: &[String]);
+    fn render_hard_break(&mut self, out: &mut String);
+    fn render_paragraph_break(&mut self, out: &mut String);
+    fn render_horizontal_rule(&mut self, out: &mut String);
+    fn render_newline(&mut self, out: &mut String);
+    fn render_comment(&mut self, out: &mut String, text: &str);
+    /// A block of content marked with a class, as in `::: center`.
+    fn render_div(&mut self, out: &mut String, class: &str, content: &str);
+
+    /// Closes the lists left open by `render_list_item`; called before any
+    /// token that cannot continue a list and at the end of the stream.
+    fn end_lists(&mut self, _out: &mut String) {}
+
+    /// Closes the quotes left open by `render_blockquote`.
+    fn end_quotes(&mut self, _out: &mut String) {}
+}
+
+/// A guess at the output length per token, used to size the output up
+/// front so it is rarely reallocated.
+const BYTES_PER_TOKEN: usize = 32;
+
+/// Walks the token stream, handing each token to the renderer and
+/// collecting the output in one string.
+pub fn render<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    tokens: impl IntoIterator<Item = Token>,
+) -> String {
+    let mut output = String::new();
+    render_into(renderer, tokens, &mut output);
+    output
+}
+
+/// Like `render`, but appends the output to `out`, growing it once up
+/// front for the whole stream.
+pub fn render_into<R: Renderer + ?Sized>(
+    renderer: &mut R,
+    tokens: impl IntoIterator<Item = Token>,
+    out: &mut String,
+) {
+    let tokens = tokens.into_iter();
+    out.reserve(tokens.size_hint().0 * BYTES_PER_TOKEN);
+    let Ok(()) = render_each(renderer, tokens, out, |_| Ok::<(), Infallible>(()));
+}
+
+/// Like `render`, but writes each piece of output as soon as it is ready,
+/// so neither the tokens nor the output need to be held in memory.
+pub fn render_to_writer<R: Renderer + ?Sized, W: Write>(
+    renderer: &mut R,
+    tokens: impl IntoIterator<Item = Token>,
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut buffer = String::new();
+    render_each(renderer, tokens, &mut buffer, |buffer| {
+        writer.write_all(buffer.as_bytes())?;
+        buffer.clear();
+        Ok(())
+    })
+}
+
+/// Renders the tokens into `out`, calling `flush` after each one so a
+/// streaming caller can hand the output on and reuse the buffer.
+fn render_each<R: Renderer + ?Sized, E>(
+    renderer: &mut R,
+    tokens: impl IntoIterator<Item = Token>,
+    out: &mut String,
+    mut flush: impl FnMut(&mut String) -> Result<(), E>,
+) -> Result<(), E> {
+    // Whether the output so far ends with a space, including what has
+    // already been flushed.
+    let ends_with_space = Cell::new(false);
+    let mut flush = |out: &mut String| {
+        if let Some(last) = out.chars().last() {
+            ends_with_space.set(last == ' ');
+        }
+        flush(out)
+    };
+    let mut tokens = tokens.into_iter().peekable();
+    let mut previous_inline = false;
+    while let Some(mut token) = tokens.next() {
+        // A run of newlines is a blank line, whichever way the token
+        // stream was produced.
+        if token == Token::Newline && tokens.peek() == Some(&Token::Newline) {
+            while tokens.next_if_eq(&Token::Newline).is_some() {}
+            token = Token::ParagraphBreak;
+        }
+        // A single newline between two pieces of running text is a soft
+        // break and joins the lines of the paragraph. The joining space is
+        // passed through `render_text` like any other text.
+        let soft_break =
+            token == Token::Newline && previous_inline && tokens.peek().is_some_and(is_inline);
+        previous_inline = is_inline(&token);
+        if soft_break {
+            let after_space = out
+                .chars()
+                .last()
+                .map_or(ends_with_space.get(), |last| last == ' ');
+            if !after_space {
+                renderer.render_text(out, " ");
+                flush(out)?;
+            }
+            continue;
+        }
+        if !matches!(
+            token,
+            Token::ListItem(..)
+                | Token::TaskItem(..)
+                | Token::DefinitionItem { .. }
+                | Token::Newline
+                | Token::ParagraphBreak
+        ) {
+            renderer.end_lists(out);
+        }
+        if !matches!(token, Token::BlockQuote(..) | Token::Newline) {
+            renderer.end_quotes(out);
+        }
+        render_token(renderer, out, token);
+        flush(out)?;
+    }
+    renderer.end_lists(out);
+    renderer.end_quotes(out);
+    flush(out)
+}
+
+fn is_inline(token: &Token) -> bool {
+    match token {
+        Token::Unterminated(construct, _) => !matches!(construct, Construct::CodeBlock(_)),
+        _ => matches!(
+            token,
+            Token::Bold(_)
+                | Token::Italic(_)
+                | Token::BoldItalic(_)
+                | Token::Strikethrough(_)
+                | Token::Link(..)
+                | Token::Footnote(_)
+                | Token::Comment(_)
+                | Token::Code(_)
+                | Token::InlineMath(_)
+                | Token::DisplayMath(_)
+                | Token::Text(_)
+        ),
+    }
+}
+
+fn render_token<R: Renderer + ?Sized>(renderer: &mut R, out: &mut String, token: Token) {
+    match token {
+        Token::Header(children, _) if children.is_empty() => {}
+        Token::Header(children, level) => {
+            let content = render_inline(renderer, children);
+            renderer.render_header(out, &content, level)
+        }
+        Token::Bold(children) => {
+            let content = render_inline(renderer, children);
+            renderer.render_bold(out, &content)
+        }
+        Token::Italic(children) => {
+            let content = render_inline(renderer, children);
+            renderer.render_italic(out, &content)
+        }
+        Token::BoldItalic(children) => {
+            let content = render_inline(renderer, children);
+            renderer.render_bold_italic(out, &content)
+        }
+        Token::Strikethrough(children) => {
+            let content = render_inline(renderer, children);
+            renderer.render_strikethrough(out, &content)
+        }
+        Token::Link(text, url, None) if text == url => renderer.render_url(out, &url),
+        Token::Link(text, url, title) => {
+            let mut content = String::new();
+            renderer.render_text(&mut content, &text);
+            renderer.render_link(out, &content, &url, title.as_deref())
+        }
+        Token::Image(alt, path) => renderer.render_image(out, &alt, &path),
+        Token::Footnote(children) => {
+            let content = render_inline(renderer, children);
+            renderer.render_footnote(out, &content)
+        }
+        Token::ListItem(children, number, indent) => {
+            let content = render_inline(renderer, children);
+            renderer.render_list_item(out, &content, number, indent)
+        }
+        Token::TaskItem(children, checked, indent) => {
+            let content = render_inline(renderer, children);
+            renderer.render_task_item(out, &content, checked, indent)
+        }
+        Token::BlockQuote(children, depth) => {
+            let content = render_inline(renderer, children);
+            renderer.render_blockquote(out, &content, depth)
+        }
+        Token::Code(code) => renderer.render_code(out, &code),
+        Token::InlineMath(formula) => renderer.render_inline_math(out, &formula),
+        Token::DisplayMath(formula) => renderer.render_display_math(out, &formula),
+        Token::CodeBlock(code, language)
+        | Token::Unterminated(Construct::CodeBlock(language), code) => {
+            renderer.render_code_block(out, &code, language.as_deref())
+        }
+        Token::RawLatex(latex) => renderer.render_raw_latex(out, &latex),
+        Token::Text(text) | Token::Unterminated(_, text) => renderer.render_text(out, &text),
+        Token::Table {
+            headers,
+            rows,
+            alignments,
+        } => renderer.render_table(out, &headers, &rows, &alignments),
+        Token::DefinitionItem { term, definitions } => {
+            let term = render_inline(renderer, term);
+            let definitions: Vec<String> = definitions
+                .into_iter()
+                .map(|definition| render_inline(renderer, definition))
+                .collect();
+            renderer.render_definition_item(out, &term, &definitions)
+        }
+        Token::HardBreak => renderer.render_hard_break(out),
+        Token::ParagraphBreak => renderer.render_paragraph_break(out),
+        Token::HorizontalRule => renderer.render_horizontal_rule(out),
+        Token::Newline => renderer.render_newline(out),
+        Token::Comment(text) => renderer.render_comment(out, &text),
+        Token::Div(class, children) => {
+            let content = render(renderer, children);
+            renderer.render_div(out, &class, &content)
+        }
+        // Metadata belongs to the document around the body, not in it.
+        Token::FrontMatter(_) => {}
+    }
+}
+
+/// Renders the children of a token into the content its renderer method
+/// wraps.
+fn render_inline<R: Renderer + ?Sized>(renderer: &mut R, tokens: Vec<Token>) -> String {
+    let mut content = String::new();
+    for token in tokens {
+        render_token(renderer, &mut content, token);
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::Tokenizer;
+    use std::fmt::Write;
+
+    /// Drops all markup and keeps the words.
+    struct PlainTextRenderer;
+
+    impl Renderer for PlainTextRenderer {
+        fn render_text(&mut self, out: &mut String, text: &str) {
+            out.push_str(text);
+        }
+        fn render_header(&mut self, out: &mut String, content: &str, _level: u8) {
+            out.push_str(&content.to_uppercase());
+            out.push('\n');
+        }
+        fn render_bold(&mut self, out: &mut String, content: &str) {
+            out.push_str(content);
+        }
+        fn render_italic(&mut self, out: &mut String, content: &str) {
+            out.push_str(content);
+        }
+        fn render_bold_italic(&mut self, out: &mut String, content: &str) {
+            out.push_str(content);
+        }
+        fn render_strikethrough(&mut self, out: &mut String, content: &str) {
+            out.push_str(content);
+        }
+        fn render_link(&mut self, out: &mut String, content: &str, url: &str, _: Option<&str>) {
+            write!(out, "{} <{}>", content, url).unwrap();
+        }
+        fn render_url(&mut self, out: &mut String, url: &str) {
+            out.push_str(url);
+        }
+        fn render_image(&mut self, out: &mut String, alt: &str, _path: &str) {
+            out.push_str(alt);
+        }
+        fn render_footnote(&mut self, out: &mut String, content: &str) {
+            write!(out, " ({})", content).unwrap();
+        }
+        fn render_list_item(
+            &mut self,
+            out: &mut String,
+            content: &str,
+            _: Option<(u32, Numbering)>,
+            indent: usize,
+        ) {
+            write!(out, "{}* {}", " ".repeat(indent), content).unwrap();
+        }
+        fn render_task_item(
+            &mut self,
+            out: &mut String,
+            content: &str,
+            checked: bool,
+            indent: usize,
+        ) {
+            let mark = if checked { 'x' } else { ' ' };
+            write!(out, "{}[{}] {}", " ".repeat(indent), mark, content).unwrap();
+        }
+        fn render_blockquote(&mut self, out: &mut String, content: &str, depth: usize) {
+            out.push_str(&"| ".repeat(depth));
+            out.push_str(content);
+        }
+        fn render_code(&mut self, out: &mut String, code: &str) {
+            out.push_str(code);
+        }
+        fn render_code_block(&mut self, out: &mut String, code: &str, _language: Option<&str>) {
+            out.push_str(code);
+            out.push('\n');
+        }
+        fn render_raw_latex(&mut self, _out: &mut String, _latex: &str) {}
+        fn render_inline_math(&mut self, out: &mut String, formula: &str) {
+            out.push_str(formula);
+        }
+        fn render_display_math(&mut self, out: &mut String, formula: &str) {
+            out.push_str(formula);
+        }
+        fn render_table(
+            &mut self,
+            out: &mut String,
+            headers: &[String],
+            rows: &[Vec<String>],
+            _: &[Align],
+        ) {
+            for cells in std::iter::once(headers).chain(rows.iter().map(Vec::as_slice)) {
+                out.push_str(&cells.join("\t"));
+                out.push('\n');
+            }
+        }
+        fn render_definition_item(&mut self, out: &mut String, term: &str, definitions: &[String]) {
+            writeln!(out, "{}: {}", term, definitions.join("; ")).unwrap();
+        }
+        fn render_hard_break(&mut self, out: &mut String) {
+            out.push('\n');
+        }
+        fn render_paragraph_break(&mut self, out: &mut String) {
+            out.push_str("\n\n");
+        }
+        fn render_horizontal_rule(&mut self, out: &mut String) {
+            out.push_str("----\n");
+        }
+        fn render_newline(&mut self, out: &mut String) {
+            out.push('\n');
+        }
+        fn render_comment(&mut self, _out: &mut String, _text: &str) {}
+        fn render_div(&mut self, out: &mut String, _class: &str, content: &str) {
+            out.push_str(content);
+            out.push('\n');
+        }
+    }
+
+    #[test]
+    fn test_plain_text_renderer() {
+        let mut tokenizer = Tokenizer::new(
+            "# Notes on *rust*\nSome **bold** text\nand [a link](http://x.io).\n\n- one\n- [x] two",
+        );
+        assert_eq!(
+            render(&mut PlainTextRenderer, tokenizer.tokenize()),
+            "NOTES ON RUST\n\nSome bold text and a link <http://x.io>.\n\n* one\n[x] two"
+        );
+    }
+}
@@ -1,38 +1,320 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
 use std::str::Chars;
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq)]
+use crate::error::ConvertError;
+
+/// A Markdown construct whose closing delimiter is missing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Construct {
+    Link,
+    Emphasis,
+    Code,
+    CodeBlock(Option<String>), //(language)
+}
+
+/// How an ordered list counts, taken from the marker of its items:
+/// `1.`, `a.`, `A.`, `i.` or `I.`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Numbering {
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+/// Column alignment taken from a table's delimiter row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Header(String, u8),
-    Bold(String),
-    Italic(String),
-    Link(String, String), //(text, url)
-    ListItem(String, bool),
+    Header(Vec<Token>, u8),
+    Bold(Vec<Token>),
+    Italic(Vec<Token>),
+    BoldItalic(Vec<Token>),
+    Strikethrough(Vec<Token>),
+    Link(String, String, Option<String>), //(text, url, title)
+    Footnote(Vec<Token>),                 //(note)
+    Image(String, String),                //(alt, path)
+    ListItem(Vec<Token>, Option<(u32, Numbering)>, usize), //(content, number, indent); bullets have no number
+    TaskItem(Vec<Token>, bool, usize),                     //(content, checked, indent)
+    BlockQuote(Vec<Token>, usize),                         //(content, depth)
+    Code(String),
+    InlineMath(String),
+    DisplayMath(String),
+    CodeBlock(String, Option<String>), //(code, language)
+    RawLatex(String),                  // a ```latex block, passed through as is
     Text(String),
+    Table {
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+        alignments: Vec<Align>,
+    },
+    DefinitionItem {
+        term: Vec<Token>,
+        definitions: Vec<Vec<Token>>,
+    },
+    HorizontalRule,
+    HardBreak,
+    ParagraphBreak,
+    Unterminated(Construct, String), //(construct, source text)
     Newline,
+    Comment(String),                    //(text between `<!--` and `-->`)
+    FrontMatter(Vec<(String, String)>), //(key, value) pairs of a leading `---` block
+    Div(String, Vec<Token>),            //(class, content) of a `::: class` block
+}
+
+impl Token {
+    /// The tokens nested inside a header, list item, block quote, footnote,
+    /// emphasis or div.
+    pub fn children(&self) -> impl Iterator<Item = &Token> {
+        let (children, definitions): (&[Token], &[Vec<Token>]) = match self {
+            Token::Bold(children)
+            | Token::Italic(children)
+            | Token::BoldItalic(children)
+            | Token::Strikethrough(children)
+            | Token::Footnote(children) => (children, &[]),
+            Token::Header(children, _)
+            | Token::ListItem(children, ..)
+            | Token::TaskItem(children, ..)
+            | Token::BlockQuote(children, _)
+            | Token::Div(_, children) => (children, &[]),
+            Token::DefinitionItem { term, definitions } => (term, definitions),
+            _ => (&[], &[]),
+        };
+        children.iter().chain(definitions.iter().flatten())
+    }
+}
+
+/// A region of the Markdown source. Lines and columns are 1-based and the
+/// end position is exclusive. Columns count characters rather than bytes,
+/// so `é` or `日` advances the column by one, as in most editors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+/// A value together with the source span it was read from.
+#[derive(Debug, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+/// The characters of the input with `\r\n` and lone `\r` line endings
+/// read as `\n`.
+#[derive(Clone)]
+struct SourceChars<'a>(Chars<'a>);
+
+impl Iterator for SourceChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self.0.next()? {
+            '\r' => {
+                if self.0.clone().next() == Some('\n') {
+                    self.0.next();
+                }
+                Some('\n')
+            }
+            ch => Some(ch),
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Tokenizer<'a> {
-    input: Chars<'a>,
+    input: SourceChars<'a>,
     current: Option<char>,
+    previous: Option<char>,
+    line: usize,
+    column: usize,
+    inline: bool, // set while tokenizing the content of a header, list item or quote
+    strict: bool, // report unclosed code spans instead of reading them as text
+    line_has_text: bool, // whether the current line has anything but whitespace so far
+    after_blank_line: bool, // whether the line before the current one was blank
+    line_is_item: bool, // whether the current line is a list item
+    after_list_item: bool, // whether the line before the current one was a list item
+    references: Arc<HashMap<String, (String, Option<String>)>>, // link definitions (url, title), keyed by lowercased id
+    footnotes: Arc<HashMap<String, String>>, // footnote text, keyed by lowercased id
+    pending: Option<Token>,                  // read ahead while merging text, not yet returned
+    started: bool,                           // whether any token has been returned yet
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::with_definitions(
+            input,
+            Arc::new(Self::link_definitions(input)),
+            Arc::new(Self::footnote_definitions(input)),
+        )
+    }
+
+    fn with_definitions(
+        input: &'a str,
+        references: Arc<HashMap<String, (String, Option<String>)>>,
+        footnotes: Arc<HashMap<String, String>>,
+    ) -> Self {
         let mut tokenizer = Tokenizer {
-            input: input.chars(),
+            input: SourceChars(input.chars()),
             current: None,
+            previous: None,
+            line: 1,
+            column: 1,
+            inline: false,
+            strict: false,
+            line_has_text: false,
+            after_blank_line: true,
+            line_is_item: false,
+            after_list_item: false,
+            references,
+            footnotes,
+            pending: None,
+            started: false,
         };
         tokenizer.advance();
         tokenizer
     }
 
     fn advance(&mut self) {
+        match self.current {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+                self.after_blank_line = !self.line_has_text;
+                self.line_has_text = false;
+                self.after_list_item = std::mem::take(&mut self.line_is_item);
+            }
+            Some(ch) => {
+                self.column += 1;
+                self.line_has_text |= !ch.is_whitespace();
+            }
+            None => {}
+        }
+        self.previous = self.current;
         self.current = self.input.next();
     }
 
-    fn take_while<F>(&mut self, condition: F) -> String
+    /// Block constructs only start at the beginning of a line, which inline
+    /// content never has.
+    fn at_line_start(&self) -> bool {
+        !self.inline && matches!(self.previous, None | Some('\n'))
+    }
+
+    /// Whether the rest of the current line is only whitespace.
+    fn is_blank_line(&self) -> bool {
+        self.current
+            .into_iter()
+            .chain(self.input.clone())
+            .take_while(|&ch| ch != '\n')
+            .all(char::is_whitespace)
+    }
+
+    /// Tokenizes the text of a header, list item or block quote for the
+    /// inline markup it contains.
+    fn inline_tokens(&self, text: &str) -> Vec<Token> {
+        self.inline_tokenizer(text).tokenize()
+    }
+
+    fn inline_tokenizer<'t>(&self, text: &'t str) -> Tokenizer<'t> {
+        let mut tokenizer = Tokenizer::with_definitions(
+            text,
+            Arc::clone(&self.references),
+            Arc::clone(&self.footnotes),
+        );
+        tokenizer.inline = true;
+        tokenizer.strict = self.strict;
+        tokenizer
+    }
+
+    /// Collects every `[id]: url` definition up front, so references can be
+    /// resolved even when they appear before their definition. When an id is
+    /// defined twice the first definition wins.
+    fn link_definitions(input: &str) -> HashMap<String, (String, Option<String>)> {
+        let mut definitions = HashMap::new();
+        for (id, url, title) in lines_outside_fences(input).filter_map(Self::parse_link_definition)
+        {
+            definitions.entry(id).or_insert((url, title));
+        }
+        definitions
+    }
+
+    /// A destination with spaces in it must be wrapped in `<>`, so that
+    /// prose like `[Note]: read this` is not taken for a definition. Lines
+    /// indented as code are never definitions.
+    fn parse_link_definition(line: &str) -> Option<(String, String, Option<String>)> {
+        if Self::code_indent(line.chars()).is_some() {
+            return None;
+        }
+        let rest = line.trim_start().strip_prefix('[')?;
+        let (id, destination) = rest.split_once("]:")?;
+        let (url, title) = split_link_title(destination.trim());
+        let url = match url.strip_prefix('<').and_then(|url| url.strip_suffix('>')) {
+            Some(url) => url,
+            None if url.contains(char::is_whitespace) => return None,
+            None => url,
+        };
+        if id.trim().is_empty() || id.starts_with('^') || id.contains(['[', ']']) || url.is_empty()
+        {
+            return None;
+        }
+        Some((id.trim().to_lowercase(), url.to_string(), title))
+    }
+
+    /// Collects every `[^id]: note` footnote definition up front, the same
+    /// way as link definitions.
+    fn footnote_definitions(input: &str) -> HashMap<String, String> {
+        let mut definitions = HashMap::new();
+        for (id, note) in lines_outside_fences(input).filter_map(Self::parse_footnote_definition) {
+            definitions.entry(id).or_insert(note);
+        }
+        definitions
+    }
+
+    fn parse_footnote_definition(line: &str) -> Option<(String, String)> {
+        if Self::code_indent(line.chars()).is_some() {
+            return None;
+        }
+        let rest = line.trim_start().strip_prefix("[^")?;
+        let (id, note) = rest.split_once("]:")?;
+        let note = note.trim();
+        if id.trim().is_empty() || id.contains(['[', ']']) || note.is_empty() {
+            return None;
+        }
+        Some((id.trim().to_lowercase(), note.to_string()))
+    }
+
+    fn is_definition(&self) -> bool {
+        if !self.at_line_start() {
+            return false;
+        }
+        let mut line = self.current.into_iter().chain(self.input.clone());
+        if line.find(|&ch| ch != ' ' && ch != '\t') != Some('[') {
+            return false;
+        }
+        let line: String = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .take_while(|&ch| ch != '\n')
+            .collect();
+        Self::parse_link_definition(&line).is_some()
+            || Self::parse_footnote_definition(&line).is_some()
+    }
+
+    fn take_while<F>(&mut self, mut condition: F) -> String
     where
-        F: Fn(char) -> bool,
+        F: FnMut(char) -> bool,
     {
         let mut result = String::new();
         while let Some(ch) = self.current {
@@ -46,200 +328,3007 @@ impl<'a> Tokenizer<'a> {
         result
     }
 
+    fn take_count(&mut self, count: usize) -> String {
+        let mut result = String::new();
+        for _ in 0..count {
+            match self.current {
+                Some(ch) => {
+                    result.push(ch);
+                    self.advance();
+                }
+                None => break,
+            }
+        }
+        result
+    }
+
+    /// Returns how many characters precede the next run of exactly `count`
+    /// `delimiter` characters on the current line, if there is one. A run of
+    /// underscores followed by a letter or digit is intra-word and never closes,
+    /// and a backslash-escaped delimiter is text.
+    fn closing_run_distance(&self, delimiter: char, count: usize) -> Option<usize> {
+        let mut chars = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .peekable();
+        let mut distance = 0;
+        while let Some(ch) = chars.next() {
+            if ch == '\n' {
+                return None;
+            }
+            if ch == '\\' && chars.next_if(|&ch| ch != '\n').is_some() {
+                distance += 2;
+                continue;
+            }
+            if ch == delimiter {
+                let mut run = 1;
+                while chars.peek() == Some(&delimiter) {
+                    chars.next();
+                    run += 1;
+                }
+                let intraword =
+                    delimiter == '_' && chars.peek().is_some_and(|ch| ch.is_alphanumeric());
+                if run == count && !intraword {
+                    return Some(distance);
+                }
+                distance += run;
+            } else {
+                distance += 1;
+            }
+        }
+        None
+    }
+
+    /// Where a longer run of `delimiter` that touches text on both sides
+    /// starts, so its first `count` markers can close emphasis and the rest
+    /// open the next one, as in `**bold***italic*`. Underscore runs never
+    /// split, since an underscore between letters is not a marker.
+    fn split_run_distance(&self, delimiter: char, count: usize) -> Option<usize> {
+        if delimiter == '_' {
+            return None;
+        }
+        let mut chars = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .take_while(|&ch| ch != '\n')
+            .peekable();
+        let mut distance = 0;
+        let mut before = None;
+        while let Some(ch) = chars.next() {
+            if ch == '\\' {
+                if let Some(escaped) = chars.next() {
+                    distance += 2;
+                    before = Some(escaped);
+                    continue;
+                }
+            }
+            if ch != delimiter {
+                distance += 1;
+                before = Some(ch);
+                continue;
+            }
+            let mut run = 1;
+            while chars.next_if_eq(&delimiter).is_some() {
+                run += 1;
+            }
+            let touches_text = before.is_some_and(|ch: char| !ch.is_whitespace())
+                && chars.peek().is_some_and(|ch| !ch.is_whitespace());
+            if run > count && touches_text {
+                return Some(distance);
+            }
+            distance += run;
+            before = Some(delimiter);
+        }
+        None
+    }
+
     pub fn tokenize(&mut self) -> Vec<Token> {
+        self.by_ref().collect()
+    }
+
+    /// Tokenizes the input, recording the source span of each token.
+    pub fn tokenize_spanned(&mut self) -> Vec<Spanned<Token>> {
         let mut tokens = Vec::new();
-        while let Some(ch) = self.current {
-            match ch {
-                '#' => tokens.push(self.tokenize_header()),
-                '*' => {
-                    if self.is_list_item() {
-                        tokens.push(self.tokenize_list_item(false));
-                    } else {
-                        tokens.push(self.tokenize_bold_or_italic())
-                    }
+        loop {
+            self.skip_leading_lines();
+            let (start_line, start_col) = (self.line, self.column);
+            match self.next_token() {
+                Some(node) => {
+                    self.started = true;
+                    tokens.push(Spanned {
+                        node,
+                        span: Span {
+                            start_line,
+                            start_col,
+                            end_line: self.line,
+                            end_col: self.column,
+                        },
+                    });
                 }
-                '[' => tokens.push(self.tokenize_link()),
-                '1'..='9' => {
-                    if self.is_numbered_list() {
-                        self.advance();
-                        self.advance();
-                        tokens.push(self.tokenize_list_item(true));
-                    } else {
-                        tokens.push(self.tokenize_text())
-                    }
+                None => return tokens,
+            }
+        }
+    }
+
+    /// Like `tokenize`, but fails on the first emphasis, link, code
+    /// span or code block that is never closed instead of recovering from
+    /// it. The span points at the opening delimiter, or at the enclosing
+    /// header, list item or quote when the construct is nested in one.
+    pub fn tokenize_checked(&mut self) -> Result<Vec<Token>, Spanned<ConvertError>> {
+        let mut checker = self.clone();
+        checker.strict = true;
+        for Spanned { node, span } in checker.tokenize_spanned() {
+            if let Some((construct, text)) = unterminated(&node) {
+                return Err(Spanned {
+                    node: ConvertError::unterminated(construct, text.clone()),
+                    span,
+                });
+            }
+        }
+        Ok(self.tokenize())
+    }
+
+    /// Blank lines and definitions before the first token separate
+    /// nothing, so they are skipped without producing tokens.
+    fn skip_leading_lines(&mut self) {
+        if self.started || self.inline {
+            return;
+        }
+        while self.current.is_some() && (self.is_blank_line() || self.is_definition()) {
+            self.take_while(|ch| ch != '\n');
+            self.advance();
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        self.skip_leading_lines();
+        if self.previous.is_none() && !self.inline {
+            if let Some(token) = self.tokenize_front_matter() {
+                return Some(token);
+            }
+        }
+        let ch = self.current?;
+        if self.is_definition() {
+            // Definitions were collected in `new`; they render as nothing.
+            self.take_while(|ch| ch != '\n');
+            self.advance();
+            return self.next_token();
+        }
+        if self.at_line_start() && ch != '\n' && self.is_blank_line() {
+            // A line of only spaces is an empty line; the spaces are dropped.
+            self.take_while(|ch| ch != '\n');
+            return self.next_token();
+        }
+        if self.is_table() {
+            return Some(self.tokenize_table());
+        }
+        if self.is_definition_term() {
+            return Some(self.tokenize_definition_item());
+        }
+        if let Some(level) = self.setext_level() {
+            return Some(self.tokenize_setext_header(level));
+        }
+        Some(match ch {
+            '-' | '*' | '_' if self.is_horizontal_rule() => {
+                self.take_while(|ch| ch != '\n');
+                Token::HorizontalRule
+            }
+            '#' if self.is_header() => self.tokenize_header(),
+            '*' => {
+                if self.is_list_item() {
+                    self.tokenize_list_item(0)
+                } else {
+                    self.tokenize_emphasis('*')
                 }
-                '-' => tokens.push(self.tokenize_list_item(false)),
-                '\n' => {
-                    tokens.push(Token::Newline);
-                    self.advance();
+            }
+            '\\' if self.is_backslash_break() => {
+                self.take_count(2);
+                Token::HardBreak
+            }
+            '\\' if self.is_escape() => {
+                self.advance();
+                Token::Text(self.take_count(1))
+            }
+            '_' if self.is_underscore_opener() => self.tokenize_emphasis('_'),
+            '~' if self.is_strikethrough() => self.tokenize_strikethrough(),
+            '$' if self.math().is_some() => self.tokenize_math(),
+            '[' => self.tokenize_link(),
+            '<' if self.comment_length().is_some() => self.tokenize_comment(),
+            '<' | 'h' if self.autolink().is_some() => self.tokenize_autolink(),
+            '!' if self.is_image() => self.tokenize_image(),
+            '`' => {
+                if self.is_code_fence() {
+                    self.tokenize_code_block()
+                } else {
+                    self.tokenize_code_span()
                 }
-                _ => tokens.push(self.tokenize_text()),
             }
+            '0'..='9' | 'a'..='z' | 'A'..='Z' if self.is_numbered_list() => {
+                self.tokenize_list_item(0)
+            }
+            '-' if self.list_indent().is_some() => self.tokenize_list_item(0),
+            ' ' if self.is_hard_break() => {
+                self.take_while(|ch| ch == ' ');
+                self.advance();
+                Token::HardBreak
+            }
+            ' ' | '\t' if self.is_indented_code() => self.tokenize_indented_code(),
+            ' ' | '\t' => match self.list_indent() {
+                Some(indent) => {
+                    self.take_while(|ch| ch == ' ' || ch == '\t');
+                    self.tokenize_list_item(indent)
+                }
+                None => self.tokenize_text(),
+            },
+            '>' if self.at_line_start() => self.tokenize_blockquote(),
+            ':' if self.div_length().is_some() => self.tokenize_div(),
+            '\n' if self.is_paragraph_break() => self.tokenize_paragraph_break(),
+            '\n' => {
+                self.advance();
+                Token::Newline
+            }
+            _ => self.tokenize_text(),
+        })
+    }
+
+    /// A run of `#` at the start of a line opens a header only when a space
+    /// or the end of the line follows it, so `#hashtag` stays text.
+    fn is_header(&self) -> bool {
+        if !self.at_line_start() || self.current != Some('#') {
+            return false;
         }
-        tokens
+        let mut lookahead = self.input.clone().skip_while(|&ch| ch == '#');
+        matches!(lookahead.next(), None | Some(' ' | '\t' | '\n'))
     }
 
     fn tokenize_header(&mut self) -> Token {
-        let level = self.take_while(|ch| ch == '#').len() as u8;
-        self.skip_whitespace();
+        let hashes = self.take_while(|ch| ch == '#').len();
+        let level = u8::try_from(hashes).unwrap_or(u8::MAX);
         let text = self.take_while(|ch| ch != '\n');
-        Token::Header(text.trim().to_string(), level)
+        Token::Header(self.inline_tokens(text.trim()), level)
     }
 
-    fn tokenize_bold_or_italic(&mut self) -> Token {
-        self.advance();
-        let is_bold = if self.current == Some('*') {
-            self.advance();
-            true
-        } else {
-            false
-        };
-
-        let text = self.take_while(|ch| ch != '*');
-        self.advance();
-        if is_bold {
-            self.advance();
+    /// Returns the header level when the current line of text is underlined
+    /// by a following line made only of `=` (level 1) or `-` (level 2).
+    fn setext_level(&self) -> Option<u8> {
+        if !self.at_line_start() || self.starts_block() {
+            return None;
         }
+        let mut lookahead = self.current.into_iter().chain(self.input.clone());
+        // Count rather than stop early, so the whole line is consumed.
+        let visible = lookahead
+            .by_ref()
+            .take_while(|&ch| ch != '\n')
+            .filter(|ch| !ch.is_whitespace())
+            .count();
+        if visible == 0 {
+            return None;
+        }
+        // The underline is one unbroken run of `=` or `-`, optionally
+        // surrounded by whitespace.
+        let mut marker = None;
+        let mut ended = false;
+        for ch in lookahead.take_while(|&ch| ch != '\n') {
+            if ch.is_whitespace() {
+                ended = marker.is_some();
+            } else if ended || !matches!(ch, '=' | '-') || marker.is_some_and(|m| m != ch) {
+                return None;
+            } else {
+                marker = Some(ch);
+            }
+        }
+        match marker {
+            Some('=') => Some(1),
+            Some('-') => Some(2),
+            _ => None,
+        }
+    }
 
-        if is_bold {
-            Token::Bold(self.clean_text(text))
-        } else {
-            Token::Italic(self.clean_text(text))
+    fn starts_block(&self) -> bool {
+        match self.current {
+            Some('>') => true,
+            Some(' ' | '\t') if self.is_indented_code() => true,
+            Some('#') => self.is_header(),
+            Some('`') => self.is_code_fence(),
+            _ => {
+                self.is_horizontal_rule()
+                    || self.is_list_item()
+                    || self.is_numbered_list()
+                    || self.list_indent().is_some()
+            }
         }
     }
 
-    fn tokenize_link(&mut self) -> Token {
-        self.advance();
-        let text = self.take_while(|ch| ch != ']');
-        self.advance();
-        self.advance();
-        let url = self.take_while(|ch| ch != ')');
+    fn tokenize_setext_header(&mut self, level: u8) -> Token {
+        let text = self.take_while(|ch| ch != '\n');
         self.advance();
-        Token::Link(self.clean_text(text), url)
+        self.take_while(|ch| ch != '\n');
+        Token::Header(self.inline_tokens(text.trim()), level)
     }
 
-    fn is_numbered_list(&self) -> bool {
-        if self.current.map(|ch| ch.is_digit(10)).unwrap_or(false) {
-            let mut lookahead = self.input.clone();
-            if let Some('.') = lookahead.next() {
-                if let Some(ch) = lookahead.next() {
-                    return ch.is_whitespace();
-                }
-            }
+    /// A definition list item is a line of its own followed by one or more
+    /// lines starting with `: `, optionally after one blank line. Like a
+    /// code block it only starts after a blank line, so the last line of a
+    /// paragraph never becomes a term.
+    fn is_definition_term(&self) -> bool {
+        if !self.at_line_start() || !self.after_blank_line {
+            return false;
         }
-        false
+        let mut lookahead = self.current.into_iter().chain(self.input.clone());
+        let term: String = lookahead.by_ref().take_while(|&ch| ch != '\n').collect();
+        let mut next: String = lookahead.by_ref().take_while(|&ch| ch != '\n').collect();
+        if next.trim().is_empty() {
+            next = lookahead.take_while(|&ch| ch != '\n').collect();
+        }
+        definition_text(&next).is_some()
+            && !term.trim().is_empty()
+            && definition_text(&term).is_none()
+            && !self.starts_block()
+    }
+
+    /// Reads the term line and each `: definition` line under it.
+    fn tokenize_definition_item(&mut self) -> Token {
+        let term = self.take_while(|ch| ch != '\n');
+        let term = self.inline_tokens(term.trim());
+        if self
+            .input
+            .clone()
+            .take_while(|&ch| ch != '\n')
+            .all(char::is_whitespace)
+        {
+            self.advance();
+            self.take_while(|ch| ch != '\n');
+        }
+        let mut definitions = Vec::new();
+        while self.current == Some('\n') {
+            let next: String = self.input.clone().take_while(|&ch| ch != '\n').collect();
+            let Some(definition) = definition_text(&next) else {
+                break;
+            };
+            definitions.push(self.inline_tokens(definition));
+            self.advance();
+            self.take_while(|ch| ch != '\n');
+        }
+        Token::DefinitionItem { term, definitions }
     }
 
-    fn tokenize_list_item(&mut self, is_numbered: bool) -> Token {
-        if is_numbered {
-            self.take_while(|ch| ch.is_digit(10));
+    /// A table starts with a row containing `|` followed by a delimiter row
+    /// with one `---` cell per header cell.
+    fn is_table(&self) -> bool {
+        if !self.at_line_start() {
+            return false;
         }
+        let mut lookahead = self.current.into_iter().chain(self.input.clone());
+        if !lookahead
+            .clone()
+            .take_while(|&ch| ch != '\n')
+            .any(|ch| ch == '|')
+        {
+            return false;
+        }
+        let header: String = lookahead.by_ref().take_while(|&ch| ch != '\n').collect();
+        let delimiter: String = lookahead.take_while(|&ch| ch != '\n').collect();
+        header.contains('|')
+            && Self::parse_alignments(&delimiter)
+                .is_some_and(|alignments| alignments.len() == Self::split_row(&header).len())
+    }
+
+    fn tokenize_table(&mut self) -> Token {
+        let headers = Self::split_row(&self.take_while(|ch| ch != '\n'));
         self.advance();
-        self.skip_whitespace();
-        let text = self.take_while(|ch| ch != '\n');
-        Token::ListItem(self.clean_text(text), is_numbered)
+        let alignments =
+            Self::parse_alignments(&self.take_while(|ch| ch != '\n')).unwrap_or_default();
+        let mut rows = Vec::new();
+        while self.current == Some('\n') && self.next_line_is_table_row() {
+            self.advance();
+            // Every row gets exactly one cell per column: short rows are
+            // padded with empty cells and extra cells are dropped.
+            let mut row = Self::split_row(&self.take_while(|ch| ch != '\n'));
+            row.resize(headers.len(), String::new());
+            rows.push(row);
+        }
+        Token::Table {
+            headers,
+            rows,
+            alignments,
+        }
     }
 
-    fn tokenize_text(&mut self) -> Token {
-        let text = self.take_while(|ch| !matches!(ch, '#' | '*' | '[' | '\n'));
-        Token::Text(self.clean_text(text))
+    fn next_line_is_table_row(&self) -> bool {
+        let line: String = self.input.clone().take_while(|&ch| ch != '\n').collect();
+        line.contains('|') && !line.trim().is_empty()
     }
 
-    fn clean_text(&self, text: String) -> String {
-        let text = text.replace("  ", " ");
-        let text = text.replace(" -", "~--");
-        text
+    fn split_row(line: &str) -> Vec<String> {
+        let line = line.trim();
+        let line = line.strip_prefix('|').unwrap_or(line);
+        let line = line.strip_suffix('|').unwrap_or(line);
+        line.split('|')
+            .map(|cell| cell.trim().to_string())
+            .collect()
     }
 
-    fn is_list_item(&self) -> bool {
-        self.input.clone().next() == Some(' ')
+    fn parse_alignments(line: &str) -> Option<Vec<Align>> {
+        if !line.contains('-') {
+            return None;
+        }
+        Self::split_row(line)
+            .iter()
+            .map(|cell| {
+                let left = cell.starts_with(':');
+                let right = cell.ends_with(':');
+                let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+                if dashes.is_empty() || !dashes.chars().all(|ch| ch == '-') {
+                    return None;
+                }
+                Some(match (left, right) {
+                    (true, true) => Align::Center,
+                    (false, true) => Align::Right,
+                    _ => Align::Left,
+                })
+            })
+            .collect()
     }
 
-    fn skip_whitespace(&mut self) {
-        while let Some(ch) = self.current {
-            if ch.is_whitespace() {
-                self.advance();
-            } else {
+    /// Underscores only open emphasis at the start of a word, so that
+    /// `snake_case_name` stays literal.
+    fn is_underscore_opener(&self) -> bool {
+        self.current == Some('_') && !self.previous.is_some_and(|ch| ch.is_alphanumeric())
+    }
+
+    fn tokenize_emphasis(&mut self, delimiter: char) -> Token {
+        let intraword = self.previous.is_some_and(char::is_alphanumeric);
+        let marker = self.take_while(|ch| ch == delimiter);
+        if marker.len() > 3 || self.current.is_none_or(char::is_whitespace) {
+            return Token::Text(marker);
+        }
+        let closing = self
+            .closing_run_distance(delimiter, marker.len())
+            .or_else(|| self.split_run_distance(delimiter, marker.len()));
+        let length = match closing {
+            Some(length) => length,
+            // An unclosed marker inside a word, as in `a*b`, is just a star.
+            None if intraword => return Token::Text(marker),
+            None => return Token::Unterminated(Construct::Emphasis, marker),
+        };
+        let text = self.take_count(length);
+        self.take_count(marker.len());
+
+        // The closing markers match the opening ones in number, so the text
+        // in between can hold emphasis of the other strength.
+        let children = self.inline_tokens(&text);
+        match marker.len() {
+            1 => Token::Italic(children),
+            2 => Token::Bold(children),
+            _ => Token::BoldItalic(children),
+        }
+    }
+
+    /// Reads a YAML front matter block: a `---` first line, `key: value`
+    /// lines, and a closing `---` or `...` line. Only flat string values
+    /// are kept. Blank lines, `#` comments and the indented or `- ` lines
+    /// under a key, as in a block list, are skipped; any other line, or no
+    /// closing line, leaves the `---` to be read as a rule.
+    fn tokenize_front_matter(&mut self) -> Option<Token> {
+        let mut lines = self.current.into_iter().chain(self.input.clone());
+        let mut next_line = || -> Option<String> {
+            let mut line = String::new();
+            for ch in lines.by_ref() {
+                if ch == '\n' {
+                    return Some(line);
+                }
+                line.push(ch);
+            }
+            (!line.is_empty()).then_some(line)
+        };
+        let first = next_line()?;
+        if first.trim_end() != "---" {
+            return None;
+        }
+        let mut length = first.chars().count() + 1;
+        let mut fields = Vec::new();
+        let mut saw_key = false;
+        loop {
+            let line = next_line()?;
+            length += line.chars().count() + 1;
+            let line = line.trim_end();
+            if line == "---" || line == "..." {
                 break;
             }
+            let under_key = line.starts_with([' ', '\t']) || line.starts_with("- ");
+            if line.trim().is_empty()
+                || line.trim_start().starts_with('#')
+                || (saw_key && under_key)
+            {
+                continue;
+            }
+            let (key, value) = line.split_once(':')?;
+            let is_key = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-');
+            if !is_key {
+                return None;
+            }
+            saw_key = true;
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let value = ['"', '\'']
+                .into_iter()
+                .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+                .unwrap_or(value);
+            fields.push((key.to_string(), value.to_string()));
         }
+        self.take_count(length);
+        Some(Token::FrontMatter(fields))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// A thematic break is a line made of three or more of the same `-`, `*`
+    /// or `_` marker, optionally separated by spaces.
+    fn is_horizontal_rule(&self) -> bool {
+        let Some(marker) = self.current else {
+            return false;
+        };
+        if !self.at_line_start() {
+            return false;
+        }
+        let mut count = 0;
+        for ch in self.current.into_iter().chain(self.input.clone()) {
+            match ch {
+                '\n' => break,
+                ' ' | '\t' => {}
+                ch if ch == marker => count += 1,
+                _ => return false,
+            }
+        }
+        count >= 3
+    }
 
-    #[test]
-    fn test_header() {
-        let input = "# Header 1";
-        let mut tokenizer = Tokenizer::new(input);
-        let tokens = tokenizer.tokenize();
-        assert_eq!(tokens, vec![Token::Header("Header 1".to_string(), 1)]);
+    /// Returns the length of the formula starting here and whether it is
+    /// display math. `$$...$$` may span lines, while `$...$` must close on
+    /// the same line with no whitespace just inside either dollar, so prices
+    /// like `$5 and $10` stay text.
+    fn math(&self) -> Option<(usize, bool)> {
+        if self.current != Some('$') {
+            return None;
+        }
+        let mut lookahead = self.input.clone().peekable();
+        if lookahead.next_if_eq(&'$').is_some() {
+            let mut formula = String::new();
+            while let Some(ch) = lookahead.next() {
+                if ch == '$' && lookahead.peek() == Some(&'$') {
+                    return (!formula.trim().is_empty()).then(|| (formula.chars().count(), true));
+                }
+                formula.push(ch);
+            }
+            return None;
+        }
+        let formula: String = lookahead
+            .by_ref()
+            .take_while(|&ch| ch != '$' && ch != '\n')
+            .collect();
+        let closed = self.input.clone().nth(formula.chars().count()) == Some('$');
+        let trimmed =
+            !formula.starts_with(char::is_whitespace) && !formula.ends_with(char::is_whitespace);
+        let before_digit = lookahead.next().is_some_and(|ch| ch.is_ascii_digit());
+        (closed && !formula.is_empty() && trimmed && !before_digit)
+            .then(|| (formula.chars().count(), false))
     }
 
-    #[test]
-    fn test_bold() {
-        let input = "This is **bold** text.";
-        let mut tokenizer = Tokenizer::new(input);
-        let tokens = tokenizer.tokenize();
-        assert_eq!(
-            tokens,
-            vec![
-                Token::Text("This is ".to_string()),
-                Token::Bold("bold".to_string()),
-                Token::Text(" text.".to_string())
-            ]
-        );
+    fn tokenize_math(&mut self) -> Token {
+        let (length, display) = self.math().expect("caller checked for math");
+        let delimiter = if display { 2 } else { 1 };
+        self.take_count(delimiter);
+        let formula = self.take_count(length);
+        self.take_count(delimiter);
+        if display {
+            Token::DisplayMath(formula.trim().to_string())
+        } else {
+            Token::InlineMath(formula)
+        }
     }
 
-    #[test]
-    fn test_italic() {
-        let input = "This is *italic* text.";
-        let mut tokenizer = Tokenizer::new(input);
-        let tokens = tokenizer.tokenize();
-        assert_eq!(
-            tokens,
-            vec![
-                Token::Text("This is ".to_string()),
-                Token::Italic("italic".to_string()),
-                Token::Text(" text.".to_string())
-            ]
-        );
+    fn is_strikethrough(&self) -> bool {
+        self.current == Some('~') && self.input.clone().next() == Some('~')
     }
 
-    #[test]
-    fn test_link() {
-        let input = "This is a [link](http://example.com).";
-        let mut tokenizer = Tokenizer::new(input);
-        let tokens = tokenizer.tokenize();
-        assert_eq!(
-            tokens,
-            vec![
-                Token::Text("This is a ".to_string()),
-                Token::Link("link".to_string(), "http://example.com".to_string()),
-                Token::Text(".".to_string())
-            ]
-        );
+    fn tokenize_strikethrough(&mut self) -> Token {
+        let marker = self.take_count(2);
+        match self.closing_run_distance('~', 2) {
+            Some(length) => {
+                let text = self.take_count(length);
+                self.take_count(2);
+                Token::Strikethrough(self.inline_tokens(&text))
+            }
+            None => Token::Text(marker),
+        }
     }
 
-    #[test]
-    fn test_unordered_list_item() {
-        let input = "- List item";
+    fn tokenize_link(&mut self) -> Token {
+        if let Some((length, note)) = self.footnote_reference() {
+            self.take_count(length);
+            // Notes do not resolve footnotes of their own, so a note that
+            // refers to itself cannot recurse.
+            let mut tokenizer = self.inline_tokenizer(&note);
+            tokenizer.footnotes = Arc::default();
+            return Token::Footnote(tokenizer.tokenize());
+        }
+        if let Some((length, text, (url, title))) = self.reference_link() {
+            self.take_count(length);
+            return Token::Link(collapse_whitespace(text), url, title);
+        }
+        match self.read_link_parts("") {
+            Ok((text, url, title)) => Token::Link(collapse_whitespace(text), url, title),
+            Err(token) => token,
+        }
+    }
+
+    /// Recognises `<scheme://...>` and bare `http://`/`https://` URLs,
+    /// returning how many characters they span along with the URL itself.
+    fn autolink(&self) -> Option<(usize, String)> {
+        match self.current? {
+            '<' => {
+                let url: String = self
+                    .input
+                    .clone()
+                    .take_while(|&ch| ch != '>' && !ch.is_whitespace())
+                    .collect();
+                let length = url.chars().count();
+                let closed = self.input.clone().nth(length) == Some('>');
+                (closed && url.contains("://")).then(|| (length + 2, url))
+            }
+            'h' if !self.previous.is_some_and(|ch| ch.is_alphanumeric()) => {
+                let mut scheme = self.input.clone();
+                if !"ttp".chars().all(|ch| scheme.next() == Some(ch)) {
+                    return None;
+                }
+                let candidate: String = self
+                    .current
+                    .into_iter()
+                    .chain(self.input.clone())
+                    .take_while(|&ch| !ch.is_whitespace() && ch != '<')
+                    .collect();
+                if !candidate.starts_with("http://") && !candidate.starts_with("https://") {
+                    return None;
+                }
+                // Trailing punctuation belongs to the sentence, as does a
+                // closing parenthesis that has no partner inside the URL.
+                let mut url = candidate.as_str();
+                while let Some(last) = url.chars().last() {
+                    let unbalanced =
+                        last == ')' && url.matches(')').count() > url.matches('(').count();
+                    if matches!(last, '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"') || unbalanced
+                    {
+                        url = &url[..url.len() - 1];
+                    } else {
+                        break;
+                    }
+                }
+                (!url.ends_with("://")).then(|| (url.chars().count(), url.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The length of an HTML comment starting here, up to and including
+    /// its `-->`. Comments may span lines; one that is never closed runs to
+    /// the end of the input.
+    fn comment_length(&self) -> Option<usize> {
+        if self.current != Some('<') {
+            return None;
+        }
+        let mut lookahead = self.input.clone();
+        if !"!--".chars().all(|ch| lookahead.next() == Some(ch)) {
+            return None;
+        }
+        let mut length = 4;
+        let mut dashes = 0;
+        for ch in lookahead {
+            length += 1;
+            match ch {
+                '>' if dashes >= 2 => return Some(length),
+                '-' => dashes += 1,
+                _ => dashes = 0,
+            }
+        }
+        Some(length)
+    }
+
+    fn tokenize_comment(&mut self) -> Token {
+        let length = self.comment_length().expect("caller checked for a comment");
+        let comment = self.take_count(length);
+        let comment = comment["<!--".len()..]
+            .strip_suffix("-->")
+            .unwrap_or(&comment[4..]);
+        Token::Comment(comment.trim().to_string())
+    }
+
+    fn tokenize_autolink(&mut self) -> Token {
+        let (length, url) = self.autolink().expect("caller checked for an autolink");
+        self.take_count(length);
+        Token::Link(url.clone(), url, None)
+    }
+
+    /// Resolves `[^id]` against the collected footnotes, returning the length
+    /// of the reference and the note's text. Undefined references are left
+    /// to be read as literal text.
+    fn footnote_reference(&self) -> Option<(usize, String)> {
+        let mut lookahead = self.input.clone();
+        if lookahead.next() != Some('^') {
+            return None;
+        }
+        let id: String = lookahead
+            .by_ref()
+            .take_while(|&ch| ch != ']' && ch != '\n')
+            .collect();
+        let length = id.chars().count() + 3;
+        let closed = self.input.clone().nth(length - 2) == Some(']');
+        let note = self.footnotes.get(&id.trim().to_lowercase())?;
+        closed.then(|| (length, note.clone()))
+    }
+
+    /// Resolves `[text][id]`, `[text][]` and `[text]` against the collected
+    /// definitions, returning the length of the source it spans. References
+    /// without a definition are left to be read as literal text.
+    fn reference_link(&self) -> Option<(usize, String, (String, Option<String>))> {
+        let mut lookahead = self.input.clone().peekable();
+        let text: String = lookahead
+            .by_ref()
+            .take_while(|&ch| ch != ']' && ch != '\n')
+            .collect();
+        let mut length = text.chars().count() + 2;
+        let id = match lookahead.peek() {
+            Some('(') => return None,
+            Some('[') => {
+                lookahead.next();
+                let id: String = lookahead
+                    .by_ref()
+                    .take_while(|&ch| ch != ']' && ch != '\n')
+                    .collect();
+                length += id.chars().count() + 2;
+                if id.is_empty() {
+                    text.clone()
+                } else {
+                    id
+                }
+            }
+            _ => text.clone(),
+        };
+        // `take_while` also consumed the closing `]`; make sure it was there.
+        let closed = self.input.clone().nth(length - 2) == Some(']');
+        let target = self.references.get(&id.trim().to_lowercase())?;
+        closed.then(|| (length, text, target.clone()))
+    }
+
+    fn is_image(&self) -> bool {
+        self.current == Some('!') && self.input.clone().next() == Some('[')
+    }
+
+    fn tokenize_image(&mut self) -> Token {
+        self.advance();
+        match self.read_link_parts("!") {
+            Ok((alt, path, _title)) => Token::Image(collapse_whitespace(alt), path),
+            Err(token) => token,
+        }
+    }
+
+    /// Reads `[text](url)` or `[text](url "title")`. When the closing `]` or
+    /// `)` is missing from the line the consumed source comes back as an
+    /// unterminated link, and brackets that are not followed by `(` come back
+    /// as plain text.
+    fn read_link_parts(&mut self, prefix: &str) -> Result<(String, String, Option<String>), Token> {
+        let mut source = prefix.to_string() + &self.take_count(1);
+        let text = self.take_while(|ch| ch != ']' && ch != '\n');
+        source.push_str(&text);
+        // Source that falls back to text is running text like any other.
+        if self.current != Some(']') {
+            return Err(Token::Unterminated(
+                Construct::Link,
+                collapse_whitespace(source),
+            ));
+        }
+        source.push_str(&self.take_count(1));
+        if self.current != Some('(') {
+            return Err(Token::Text(collapse_whitespace(source)));
+        }
+        source.push_str(&self.take_count(1));
+        // Parentheses in the destination must balance, as in `a_(b)`.
+        let mut depth = 0;
+        let destination = self.take_while(|ch| {
+            match ch {
+                '(' => depth += 1,
+                ')' if depth == 0 => return false,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            ch != '\n'
+        });
+        source.push_str(&destination);
+        if self.current != Some(')') {
+            return Err(Token::Unterminated(Construct::Link, source));
+        }
+        self.advance();
+        let (url, title) = split_link_title(&destination);
+        Ok((text, url.to_string(), title))
+    }
+
+    /// A run of three or more backticks at the start of a line opens a
+    /// fence, unless the info string after it has a backtick of its own.
+    fn is_code_fence(&self) -> bool {
+        if !self.at_line_start() {
+            return false;
+        }
+        let mut lookahead = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .peekable();
+        let mut fence = 0;
+        while lookahead.next_if_eq(&'`').is_some() {
+            fence += 1;
+        }
+        fence >= 3 && lookahead.take_while(|&ch| ch != '\n').all(|ch| ch != '`')
+    }
+
+    /// A line indented by four columns opens a code block when it follows a
+    /// blank line or starts the document, so it never interrupts a
+    /// paragraph. An indented list marker still makes a nested list item.
+    fn is_indented_code(&self) -> bool {
+        self.at_line_start()
+            && self.after_blank_line
+            && self.list_indent().is_none()
+            && Self::code_indent(self.current.into_iter().chain(self.input.clone())).is_some()
+    }
+
+    /// Reads the following indented lines as one code block, stripping
+    /// exactly one level of indentation from each. Blank lines between
+    /// indented lines belong to the block.
+    fn tokenize_indented_code(&mut self) -> Token {
+        let mut lines = Vec::new();
+        while let Some(indent) =
+            Self::code_indent(self.current.into_iter().chain(self.input.clone()))
+        {
+            self.take_count(indent);
+            lines.push(self.take_while(|ch| ch != '\n'));
+            let Some(blank_lines) = self.blank_lines_before_code() else {
+                break;
+            };
+            self.advance();
+            for _ in 0..blank_lines {
+                self.take_while(|ch| ch != '\n');
+                self.advance();
+                lines.push(String::new());
+            }
+        }
+        Token::CodeBlock(lines.join("\n"), None)
+    }
+
+    /// How many blank lines follow the current one before an indented code
+    /// line, if one follows at all.
+    fn blank_lines_before_code(&self) -> Option<usize> {
+        if self.current != Some('\n') {
+            return None;
+        }
+        let mut lookahead = self.input.clone();
+        let mut blank_lines = 0;
+        while Self::code_indent(lookahead.clone()).is_none() {
+            lookahead.clone().next()?;
+            if !lookahead
+                .by_ref()
+                .take_while(|&ch| ch != '\n')
+                .all(char::is_whitespace)
+            {
+                return None;
+            }
+            blank_lines += 1;
+        }
+        Some(blank_lines)
+    }
+
+    /// How many characters make up four columns of indentation at the start
+    /// of `line`, a tab counting as a full step. Blank lines have none.
+    fn code_indent(line: impl Iterator<Item = char>) -> Option<usize> {
+        let mut line = line.take_while(|&ch| ch != '\n');
+        let mut columns = 0;
+        let mut length = 0;
+        while columns < 4 {
+            match line.next()? {
+                ' ' => columns += 1,
+                '\t' => columns = 4,
+                _ => return None,
+            }
+            length += 1;
+        }
+        line.any(|ch| !ch.is_whitespace()).then_some(length)
+    }
+
+    fn tokenize_code_block(&mut self) -> Token {
+        let fence = self.take_while(|ch| ch == '`').len();
+        let info = self.take_while(|ch| ch != '\n');
+        let language = info.split_whitespace().next().unwrap_or("").to_string();
+        self.advance();
+        let mut lines = Vec::new();
+        let mut closed = false;
+        while self.current.is_some() {
+            let line = self.take_while(|ch| ch != '\n');
+            // Only a bare run at least as long as the opening one closes
+            // the block, so a longer fence can hold shorter ones.
+            let line_fence = line.trim();
+            if line_fence.len() >= fence && line_fence.chars().all(|ch| ch == '`') {
+                closed = true;
+                break;
+            }
+            lines.push(line);
+            self.advance();
+        }
+        let language = if language.is_empty() {
+            None
+        } else {
+            Some(language)
+        };
+        if closed && language.as_deref() == Some("latex") {
+            Token::RawLatex(lines.join("\n"))
+        } else if closed {
+            Token::CodeBlock(lines.join("\n"), language)
+        } else {
+            Token::Unterminated(Construct::CodeBlock(language), lines.join("\n"))
+        }
+    }
+
+    fn tokenize_code_span(&mut self) -> Token {
+        let fence = self.take_while(|ch| ch == '`');
+        match self.closing_run_distance('`', fence.len()) {
+            Some(length) => {
+                let code = self.take_count(length);
+                self.take_count(fence.len());
+                let stripped = code
+                    .strip_prefix(' ')
+                    .and_then(|code| code.strip_suffix(' '))
+                    .filter(|code| !code.trim().is_empty());
+                Token::Code(stripped.map(str::to_string).unwrap_or(code))
+            }
+            None if self.strict => Token::Unterminated(Construct::Code, fence),
+            None => Token::Text(fence),
+        }
+    }
+
+    fn is_numbered_list(&self) -> bool {
+        if !self.at_line_start() {
+            return false;
+        }
+        let mut lookahead = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .peekable();
+        Self::ordered_marker(&mut lookahead)
+            .is_some_and(|(_, numbering)| self.letter_marker_allowed(numbering, lookahead.clone()))
+            && lookahead.next().is_some_and(char::is_whitespace)
+    }
+
+    /// Letters and roman numerals also start ordinary prose, as in `J. Doe`
+    /// or `I. think`, so as list markers they cannot interrupt a paragraph,
+    /// and an uppercase one needs two spaces after it.
+    fn letter_marker_allowed(
+        &self,
+        numbering: Numbering,
+        rest: impl Iterator<Item = char>,
+    ) -> bool {
+        match numbering {
+            Numbering::Decimal => true,
+            _ if !self.after_blank_line && !self.after_list_item => false,
+            Numbering::UpperAlpha | Numbering::UpperRoman => {
+                let gap: String = rest.take(2).collect();
+                gap.starts_with('\t') || gap == "  " || gap == " \t"
+            }
+            Numbering::LowerAlpha | Numbering::LowerRoman => true,
+        }
+    }
+
+    /// Reads an ordered list marker such as `3.`, `c.` or `iv.` off the
+    /// front of `chars`, returning the item's number and numbering style.
+    fn ordered_marker(
+        chars: &mut Peekable<impl Iterator<Item = char>>,
+    ) -> Option<(u32, Numbering)> {
+        let mut marker = String::new();
+        while let Some(ch) = chars.next_if(char::is_ascii_alphanumeric) {
+            marker.push(ch);
+        }
+        if chars.next() != Some('.') {
+            return None;
+        }
+        parse_ordered_marker(&marker)
+    }
+
+    /// Returns the indentation of a list item starting on the current line,
+    /// counting a tab as four columns.
+    fn list_indent(&self) -> Option<usize> {
+        if !self.at_line_start() {
+            return None;
+        }
+        let mut lookahead = self
+            .current
+            .into_iter()
+            .chain(self.input.clone())
+            .peekable();
+        let mut indent = 0;
+        while let Some(&ch) = lookahead.peek() {
+            match ch {
+                ' ' => indent += 1,
+                '\t' => indent += 4,
+                _ => break,
+            }
+            lookahead.next();
+        }
+        let is_marker = match lookahead.peek() {
+            Some('-' | '*') => lookahead.next().is_some(),
+            _ => Self::ordered_marker(&mut lookahead).is_some_and(|(_, numbering)| {
+                self.letter_marker_allowed(numbering, lookahead.clone())
+            }),
+        };
+        let has_space = lookahead.next().is_some_and(|ch| ch == ' ' || ch == '\t');
+        if is_marker && has_space {
+            Some(indent)
+        } else {
+            None
+        }
+    }
+
+    fn tokenize_list_item(&mut self, indent: usize) -> Token {
+        self.line_is_item = true;
+        let marker = self.take_while(|ch| ch.is_ascii_alphanumeric());
+        let number = parse_ordered_marker(&marker);
+        self.advance();
+        self.skip_whitespace();
+        let text = self.take_while(|ch| ch != '\n');
+        if number.is_none() {
+            if let Some((checked, task)) = Self::task_checkbox(&text) {
+                return Token::TaskItem(self.inline_tokens(task), checked, indent);
+            }
+        }
+        Token::ListItem(self.inline_tokens(&text), number, indent)
+    }
+
+    /// Splits a `[ ]` or `[x]` checkbox off the front of a bullet's text.
+    fn task_checkbox(text: &str) -> Option<(bool, &str)> {
+        let checked = match text.get(..3)? {
+            "[ ]" => false,
+            "[x]" | "[X]" => true,
+            _ => return None,
+        };
+        let rest = &text[3..];
+        if rest.is_empty() {
+            Some((checked, rest))
+        } else {
+            rest.strip_prefix([' ', '\t'])
+                .map(|task| (checked, task.trim_start()))
+        }
+    }
+
+    /// The length of a fenced div starting here: a `::: class` line, the
+    /// content, and a closing line of colons. Divs do not nest; the first
+    /// closing line ends the div.
+    fn div_length(&self) -> Option<usize> {
+        if !self.at_line_start() {
+            return None;
+        }
+        let mut lines = self.current.into_iter().chain(self.input.clone());
+        let opening: String = lines.by_ref().take_while(|&ch| ch != '\n').collect();
+        div_class(&opening)?;
+        let mut length = opening.chars().count();
+        while lines.clone().next().is_some() {
+            let line: String = lines.by_ref().take_while(|&ch| ch != '\n').collect();
+            length += 1 + line.chars().count();
+            if is_div_fence(&line) {
+                return Some(length);
+            }
+        }
+        None
+    }
+
+    /// Reads a fenced div, tokenizing its content as blocks of its own.
+    fn tokenize_div(&mut self) -> Token {
+        let length = self.div_length().expect("caller checked for a div");
+        let source = self.take_count(length);
+        let (opening, rest) = source.split_once('\n').unwrap_or((&source, ""));
+        let class = div_class(opening).unwrap_or_default().to_string();
+        let content = rest.rsplit_once('\n').map_or("", |(content, _)| content);
+        let tokens = Tokenizer::with_definitions(
+            content,
+            Arc::clone(&self.references),
+            Arc::clone(&self.footnotes),
+        )
+        .tokenize();
+        Token::Div(class, tokens)
+    }
+
+    fn tokenize_blockquote(&mut self) -> Token {
+        let mut depth = 0;
+        while self.current == Some('>') {
+            depth += 1;
+            self.advance();
+            if self.current == Some(' ') {
+                self.advance();
+            }
+        }
+        let text = self.take_while(|ch| ch != '\n');
+        Token::BlockQuote(self.inline_tokens(&text), depth)
+    }
+
+    /// A backslash before ASCII punctuation makes that character literal.
+    fn is_escape(&self) -> bool {
+        self.current == Some('\\')
+            && self
+                .input
+                .clone()
+                .next()
+                .is_some_and(|ch| ch.is_ascii_punctuation())
+    }
+
+    /// Two or more spaces at the end of a line force a line break.
+    fn is_hard_break(&self) -> bool {
+        if self.current != Some(' ') || self.at_line_start() {
+            return false;
+        }
+        let mut lookahead = self.input.clone();
+        let mut spaces = 1;
+        loop {
+            match lookahead.next() {
+                Some(' ') => spaces += 1,
+                Some('\n') => return spaces >= 2 && !Self::line_is_blank(lookahead),
+                _ => return false,
+            }
+        }
+    }
+
+    /// A backslash at the end of a line is the other way to force a break.
+    fn is_backslash_break(&self) -> bool {
+        let mut lookahead = self.input.clone();
+        self.current == Some('\\')
+            && !self.at_line_start()
+            && lookahead.next() == Some('\n')
+            && !Self::line_is_blank(lookahead)
+    }
+
+    /// A newline followed by a blank line ends the paragraph.
+    fn is_paragraph_break(&self) -> bool {
+        let mut lookahead = self.input.clone().skip_while(|&ch| ch == ' ' || ch == '\t');
+        self.current == Some('\n') && lookahead.next() == Some('\n')
+    }
+
+    /// Consumes the newline and every blank line after it, so a run of blank
+    /// lines still separates just two paragraphs.
+    fn tokenize_paragraph_break(&mut self) -> Token {
+        while self.is_paragraph_break() {
+            self.advance();
+            self.take_while(|ch| ch == ' ' || ch == '\t');
+        }
+        self.advance();
+        Token::ParagraphBreak
+    }
+
+    /// Whether the line starting at `rest` holds nothing but whitespace;
+    /// the end of the input counts as a blank line.
+    fn line_is_blank(rest: SourceChars) -> bool {
+        rest.take_while(|&ch| ch != '\n').all(char::is_whitespace)
+    }
+
+    fn tokenize_text(&mut self) -> Token {
+        let mut text = String::new();
+        while let Some(ch) = self.current {
+            // The first character is always taken, so a marker that did not
+            // open anything is kept as text.
+            if !text.is_empty()
+                && (matches!(ch, '*' | '[' | '`' | '\n')
+                    || self.is_image()
+                    || self.is_strikethrough()
+                    || self.is_hard_break()
+                    || self.is_underscore_opener()
+                    || self.is_escape()
+                    || self.is_backslash_break()
+                    || self.math().is_some()
+                    || self.comment_length().is_some()
+                    || self.autolink().is_some())
+            {
+                break;
+            }
+            text.push(ch);
+            self.advance();
+        }
+        Token::Text(collapse_whitespace(text))
+    }
+
+    fn is_list_item(&self) -> bool {
+        self.at_line_start() && self.input.clone().next() == Some(' ')
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(ch) = self.current {
+            if ch.is_whitespace() {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Yields tokens one at a time, so large inputs can be processed without
+/// collecting the whole token stream first. Adjacent pieces of plain text,
+/// such as a word and a `*` that opened nothing, come out as one `Text`.
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.pending.take().or_else(|| self.next_token())?;
+        self.started = true;
+        let Token::Text(mut text) = token else {
+            return Some(token);
+        };
+        loop {
+            match self.next_token() {
+                Some(Token::Text(more)) => text.push_str(&more),
+                other => {
+                    self.pending = other;
+                    return Some(Token::Text(text));
+                }
+            }
+        }
+    }
+}
+
+/// Numbers an ordered list marker without its dot. A lone `i` counts as
+/// roman, every other single letter as alphabetic; roman markers are only
+/// read up to `xxxix` so ordinary words are not mistaken for numerals.
+fn parse_ordered_marker(marker: &str) -> Option<(u32, Numbering)> {
+    if !marker.is_empty() && marker.chars().all(|ch| ch.is_ascii_digit()) {
+        return Some((marker.parse().unwrap_or(u32::MAX), Numbering::Decimal));
+    }
+    let lower = marker.to_ascii_lowercase();
+    let uppercase = if marker == lower {
+        false
+    } else if marker == marker.to_ascii_uppercase() {
+        true
+    } else {
+        return None;
+    };
+    if let Some(value) = roman_value(&lower) {
+        let style = if uppercase {
+            Numbering::UpperRoman
+        } else {
+            Numbering::LowerRoman
+        };
+        return Some((value, style));
+    }
+    match lower.as_bytes() {
+        &[letter] if letter.is_ascii_lowercase() => {
+            let style = if uppercase {
+                Numbering::UpperAlpha
+            } else {
+                Numbering::LowerAlpha
+            };
+            Some((u32::from(letter - b'a') + 1, style))
+        }
+        _ => None,
+    }
+}
+
+/// The value of a lowercase roman numeral made of `i`, `v` and `x`, when it
+/// is written the standard way. A lone `v` or `x` is left to be a letter.
+fn roman_value(numeral: &str) -> Option<u32> {
+    if numeral.is_empty() || numeral == "v" || numeral == "x" {
+        return None;
+    }
+    (1..40).find(|&value| to_roman(value) == numeral)
+}
+
+pub(crate) fn to_roman(mut value: u32) -> String {
+    let mut numeral = String::new();
+    for (step, digits) in [(10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i")] {
+        while value >= step {
+            numeral.push_str(digits);
+            value -= step;
+        }
+    }
+    numeral
+}
+
+/// Collapses each run of spaces and tabs in running text to one space.
+/// Code and math are read separately and never pass through here.
+fn collapse_whitespace(text: String) -> String {
+    if !text.contains('\t') && !text.contains("  ") {
+        return text;
+    }
+    let mut collapsed = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if ch == ' ' || ch == '\t' {
+            if !collapsed.ends_with(' ') {
+                collapsed.push(' ');
+            }
+        } else {
+            collapsed.push(ch);
+        }
+    }
+    collapsed
+}
+
+/// The class of a `::: class` or `::: {.class}` line opening a div.
+fn div_class(line: &str) -> Option<&str> {
+    let rest = line.trim_end().strip_prefix(":::")?.trim_start_matches(':');
+    let class = rest.trim();
+    let class = class
+        .strip_prefix("{.")
+        .and_then(|class| class.strip_suffix('}'))
+        .unwrap_or(class);
+    let word = !class.is_empty() && !class.contains(char::is_whitespace);
+    word.then_some(class)
+}
+
+/// Whether `line` closes a div: three or more colons and nothing else.
+fn is_div_fence(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && line.chars().all(|ch| ch == ':')
+}
+
+/// The text of a `: definition` line, if `line` is one.
+fn definition_text(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix(':')?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim())
+}
+
+/// The first unclosed construct in `token` or anything nested in it.
+fn unterminated(token: &Token) -> Option<(&Construct, &String)> {
+    match token {
+        Token::Unterminated(construct, text) => Some((construct, text)),
+        _ => token.children().find_map(unterminated),
+    }
+}
+
+/// The lines of `input` outside fenced code blocks, read with the same
+/// opening and closing rules as `tokenize_code_block`.
+fn lines_outside_fences(input: &str) -> impl Iterator<Item = &str> {
+    let mut fence = 0;
+    input.split(['\n', '\r']).filter(move |line| {
+        let trimmed = line.trim();
+        if fence > 0 {
+            if trimmed.len() >= fence && trimmed.chars().all(|ch| ch == '`') {
+                fence = 0;
+            }
+            return false;
+        }
+        let run = line.chars().take_while(|&ch| ch == '`').count();
+        if run >= 3 && !line[run..].contains('`') {
+            fence = run;
+            return false;
+        }
+        true
+    })
+}
+
+/// Splits a trailing `"title"` or `'title'` off a link destination. A
+/// destination without one is returned untouched.
+fn split_link_title(destination: &str) -> (&str, Option<String>) {
+    let trimmed = destination.trim();
+    // A `<>`-wrapped destination may itself contain spaces.
+    let parts = match trimmed.find('>') {
+        Some(end) if trimmed.starts_with('<') => Some(trimmed.split_at(end + 1)),
+        _ => trimmed.split_once(char::is_whitespace),
+    };
+    if let Some((url, title)) = parts {
+        let title = title.trim_start();
+        for quote in ['"', '\''] {
+            if let Some(title) = title
+                .strip_prefix(quote)
+                .and_then(|title| title.strip_suffix(quote))
+            {
+                return (url, Some(title.to_string()));
+            }
+        }
+    }
+    (destination, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterator_yields_tokens_lazily() {
+        let mut tokenizer = Tokenizer::new("# Title\n*hi*");
+        assert_eq!(
+            tokenizer.next(),
+            Some(Token::Header(vec![Token::Text("Title".to_string())], 1))
+        );
+        assert_eq!(tokenizer.next(), Some(Token::Newline));
+        assert_eq!(
+            tokenizer.next(),
+            Some(Token::Italic(vec![Token::Text("hi".to_string())]))
+        );
+        assert_eq!(tokenizer.next(), None);
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn test_iterator_on_empty_input() {
+        assert_eq!(Tokenizer::new("").next(), None);
+    }
+
+    #[test]
+    fn test_header() {
+        let input = "# Header 1";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Header(vec![Token::Text("Header 1".to_string())], 1)]
+        );
+    }
+
+    #[test]
+    fn test_task_items() {
+        let input = "- [ ] todo\n- [x] done\n  - [X] nested\n- [link](url)";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::TaskItem(vec![Token::Text("todo".to_string())], false, 0),
+                Token::Newline,
+                Token::TaskItem(vec![Token::Text("done".to_string())], true, 0),
+                Token::Newline,
+                Token::TaskItem(vec![Token::Text("nested".to_string())], true, 2),
+                Token::Newline,
+                Token::ListItem(
+                    vec![Token::Link("link".to_string(), "url".to_string(), None)],
+                    None,
+                    0
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_inline_content() {
+        let input = "## A *word* and 1. more";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Header(
+                vec![
+                    Token::Text("A ".to_string()),
+                    Token::Italic(vec![Token::Text("word".to_string())]),
+                    Token::Text(" and 1. more".to_string())
+                ],
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn test_list_item_inline_content() {
+        let input = "- **bold** - not a bullet";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::ListItem(
+                vec![
+                    Token::Bold(vec![Token::Text("bold".to_string())]),
+                    Token::Text(" - not a bullet".to_string())
+                ],
+                None,
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn test_hash_inside_text_is_not_a_header() {
+        let input = "C# and **F**#";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("C# and ".to_string()),
+                Token::Bold(vec![Token::Text("F".to_string())]),
+                Token::Text("#".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hashes_need_a_space_to_open_a_header() {
+        let input = "#hashtag\n# Heading\n###Nope";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("#hashtag".to_string()),
+                Token::Newline,
+                Token::Header(vec![Token::Text("Heading".to_string())], 1),
+                Token::Newline,
+                Token::Text("###Nope".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_header_stays_on_its_line() {
+        let input = "#\ntext";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Header(vec![], 1),
+                Token::Newline,
+                Token::Text("text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_setext_headers() {
+        let input = "Title\n=====\nSubtitle\n---\ntext";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Header(vec![Token::Text("Title".to_string())], 1),
+                Token::Newline,
+                Token::Header(vec![Token::Text("Subtitle".to_string())], 2),
+                Token::Newline,
+                Token::Text("text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_item_before_dashes_is_not_setext() {
+        let input = "- item\n---";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::ListItem(vec![Token::Text("item".to_string())], None, 0),
+                Token::Newline,
+                Token::HorizontalRule
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dashes_with_text_are_not_an_underline() {
+        let input = "Title\n- item";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("Title".to_string()),
+                Token::Newline,
+                Token::ListItem(vec![Token::Text("item".to_string())], None, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bold() {
+        let input = "This is **bold** text.";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("This is ".to_string()),
+                Token::Bold(vec![Token::Text("bold".to_string())]),
+                Token::Text(" text.".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_italic() {
+        let input = "This is *italic* text.";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("This is ".to_string()),
+                Token::Italic(vec![Token::Text("italic".to_string())]),
+                Token::Text(" text.".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bold_italic() {
+        let input = "***x***";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::BoldItalic(vec![Token::Text("x".to_string())])]
+        );
+    }
+
+    #[test]
+    fn test_nested_emphasis() {
+        let text = |text: &str| Token::Text(text.to_string());
+        let mut tokenizer = Tokenizer::new("**a *b* c** *a **b** c*");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Bold(vec![text("a "), Token::Italic(vec![text("b")]), text(" c")]),
+                text(" "),
+                Token::Italic(vec![text("a "), Token::Bold(vec![text("b")]), text(" c")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strikethrough_mid_sentence() {
+        let input = "This is ~~deleted~~ text";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("This is ".to_string()),
+                Token::Strikethrough(vec![Token::Text("deleted".to_string())]),
+                Token::Text(" text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lone_tilde_is_text() {
+        let input = "about ~5 minutes";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::Text("about ~5 minutes".to_string())]);
+    }
+
+    #[test]
+    fn test_closing_run_can_open_the_next_emphasis() {
+        let mut tokenizer = Tokenizer::new("**bold***italic*");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Bold(vec![Token::Text("bold".to_string())]),
+                Token::Italic(vec![Token::Text("italic".to_string())])
+            ]
+        );
+        let mut tokenizer = Tokenizer::new("*a **b** c*");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![Token::Italic(vec![
+                Token::Text("a ".to_string()),
+                Token::Bold(vec![Token::Text("b".to_string())]),
+                Token::Text(" c".to_string())
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_underscore_emphasis() {
+        let input = "_italic_ and __bold__";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Italic(vec![Token::Text("italic".to_string())]),
+                Token::Text(" and ".to_string()),
+                Token::Bold(vec![Token::Text("bold".to_string())])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snake_case_is_literal() {
+        let input = "call file_name_here now";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Text("call file_name_here now".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_underscore_word_boundaries() {
+        let cases = [
+            (
+                "_x_",
+                vec![Token::Italic(vec![Token::Text("x".to_string())])],
+            ),
+            (
+                "__y__",
+                vec![Token::Bold(vec![Token::Text("y".to_string())])],
+            ),
+            ("foo_bar_baz", vec![Token::Text("foo_bar_baz".to_string())]),
+            (
+                "(_x_).",
+                vec![
+                    Token::Text("(".to_string()),
+                    Token::Italic(vec![Token::Text("x".to_string())]),
+                    Token::Text(").".to_string()),
+                ],
+            ),
+        ];
+        for (input, expected) in cases {
+            let mut tokenizer = Tokenizer::new(input);
+            assert_eq!(tokenizer.tokenize(), expected, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_autolinks() {
+        let input = "See https://example.com/a_b. Or <http://x.org/~me>!";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("See ".to_string()),
+                Token::Link(
+                    "https://example.com/a_b".to_string(),
+                    "https://example.com/a_b".to_string(),
+                    None
+                ),
+                Token::Text(". Or ".to_string()),
+                Token::Link(
+                    "http://x.org/~me".to_string(),
+                    "http://x.org/~me".to_string(),
+                    None
+                ),
+                Token::Text("!".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_non_urls_are_text() {
+        let input = "hello https:// and a <b> tag";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Text("hello https:// and a <b> tag".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_reference_links() {
+        let input = "[full][Docs], [collapsed][] and [docs].\n\n[docs]: https://example.com/docs\n[collapsed]: <http://c.io> \"Title\"";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Link(
+                    "full".to_string(),
+                    "https://example.com/docs".to_string(),
+                    None
+                ),
+                Token::Text(", ".to_string()),
+                Token::Link(
+                    "collapsed".to_string(),
+                    "http://c.io".to_string(),
+                    Some("Title".to_string())
+                ),
+                Token::Text(" and ".to_string()),
+                Token::Link(
+                    "docs".to_string(),
+                    "https://example.com/docs".to_string(),
+                    None
+                ),
+                Token::Text(".".to_string()),
+                Token::ParagraphBreak
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reference_used_before_definition() {
+        let input = "Go [here][Site].\n[site]: http://first.io\n[SITE]: http://second.io";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("Go ".to_string()),
+                Token::Link("here".to_string(), "http://first.io".to_string(), None),
+                Token::Text(".".to_string()),
+                Token::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prose_shaped_like_a_definition_is_kept() {
+        for input in [
+            "Intro\n[Update]: the server is down for maintenance\nMore",
+            "[Note]: read this carefully",
+        ] {
+            let tokens = Tokenizer::new(input).tokenize();
+            let text: String = tokens
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            assert!(text.contains("]: "), "{:?} lost text: {:?}", input, tokens);
+        }
+        let mut tokenizer = Tokenizer::new("[a]\n\n[a]: <http://x.io/a b> \"T\"");
+        assert_eq!(
+            tokenizer.tokenize()[0],
+            Token::Link(
+                "a".to_string(),
+                "http://x.io/a b".to_string(),
+                Some("T".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_definitions_inside_code_blocks_are_ignored() {
+        let input =
+            "[a] and [^n]\n\n```\n[a]: http://x.io\n[^n]: note\n```\n\n    [a]: http://y.io";
+        let tokens = Tokenizer::new(input).tokenize();
+        assert_eq!(tokens[0], Token::Text("[a] and [^n]".to_string()));
+        assert!(tokens.contains(&Token::CodeBlock(
+            "[a]: http://x.io\n[^n]: note".to_string(),
+            None
+        )));
+    }
+
+    #[test]
+    fn test_link_titles() {
+        let input = "[a](http://x.io \"hover text\") and ![b](b.png 'pic')";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Link(
+                    "a".to_string(),
+                    "http://x.io".to_string(),
+                    Some("hover text".to_string())
+                ),
+                Token::Text(" and ".to_string()),
+                Token::Image("b".to_string(), "b.png".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_footnote_references() {
+        let input = "A[^a] and [^b]\n[^a]: Note with [^a] in it.";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("A".to_string()),
+                Token::Footnote(vec![Token::Text("Note with [^a] in it.".to_string())]),
+                Token::Text(" and [^b]".to_string()),
+                Token::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reference_links_resolve_inside_list_items() {
+        let input = "- see [home]\n\n[home]: http://h.io";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens[0],
+            Token::ListItem(
+                vec![
+                    Token::Text("see ".to_string()),
+                    Token::Link("home".to_string(), "http://h.io".to_string(), None)
+                ],
+                None,
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn test_brackets_without_url_are_text() {
+        let input = "[not a link] and [x] (y) and ![alt] z";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Text(
+                "[not a link] and [x] (y) and ![alt] z".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_backslash_escapes() {
+        let input = "\\*not italic\\* \\# \\\\ \\a";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Text("*not italic* # \\ \\a".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_link() {
+        let input = "This is a [link](http://example.com).";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("This is a ".to_string()),
+                Token::Link("link".to_string(), "http://example.com".to_string(), None),
+                Token::Text(".".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_url_with_parentheses() {
+        let mut tokenizer = Tokenizer::new("[link](http://x.com/a_(b)) and ![i](a(1).png)");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Link("link".to_string(), "http://x.com/a_(b)".to_string(), None),
+                Token::Text(" and ".to_string()),
+                Token::Image("i".to_string(), "a(1).png".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_in_paragraph() {
+        let input = "See ![A diagram](img/diagram.png) here!";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("See ".to_string()),
+                Token::Image("A diagram".to_string(), "img/diagram.png".to_string()),
+                Token::Text(" here!".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_and_link() {
+        let input = "![alt](a.png) [text](b.png)";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Image("alt".to_string(), "a.png".to_string()),
+                Token::Text(" ".to_string()),
+                Token::Link("text".to_string(), "b.png".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_link() {
+        let input = "[link](http://example.com";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Unterminated(
+                Construct::Link,
+                "[link](http://example.com".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_emphasis() {
+        let input = "**open";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Unterminated(Construct::Emphasis, "**".to_string()),
+                Token::Text("open".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stray_markers_stay_in_one_text_run() {
+        let input = "a*b and C# is a language\nC# too]";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("a*b and C# is a language".to_string()),
+                Token::Newline,
+                Token::Text("C# too]".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spaced_asterisk_is_text() {
+        let input = "2 * 3";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::Text("2 * 3".to_string())]);
+    }
+
+    #[test]
+    fn test_unordered_list_item() {
+        let input = "- List item";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::ListItem(
+                vec![Token::Text("List item".to_string())],
+                None,
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn test_ordered_list_item() {
+        let input = "1. List item";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::ListItem(
+                vec![Token::Text("List item".to_string())],
+                Some((1, Numbering::Decimal)),
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lettered_and_roman_list_markers() {
+        let input = "a. first\nB.  second\niv. fourth\n  II.  nested\nmix. not a list";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        let item = |text: &str, number, indent| {
+            Token::ListItem(vec![Token::Text(text.to_string())], Some(number), indent)
+        };
+        assert_eq!(
+            tokens,
+            vec![
+                item("first", (1, Numbering::LowerAlpha), 0),
+                Token::Newline,
+                item("second", (2, Numbering::UpperAlpha), 0),
+                Token::Newline,
+                item("fourth", (4, Numbering::LowerRoman), 0),
+                Token::Newline,
+                item("nested", (2, Numbering::UpperRoman), 2),
+                Token::Newline,
+                Token::Text("mix. not a list".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_initials_at_the_start_of_a_line_are_text() {
+        for input in [
+            "as noted by\nJ. Doe in 2020",
+            "A. Smith wrote",
+            "I. think so",
+            "Some text\na. not an item",
+        ] {
+            let tokens = Tokenizer::new(input).tokenize();
+            assert!(
+                !tokens
+                    .iter()
+                    .any(|token| matches!(token, Token::ListItem(..))),
+                "{:?} became {:?}",
+                input,
+                tokens
+            );
+        }
+        let mut tokenizer = Tokenizer::new("Text\n\nA.  Smith");
+        assert_eq!(
+            tokenizer.tokenize()[2],
+            Token::ListItem(
+                vec![Token::Text("Smith".to_string())],
+                Some((1, Numbering::UpperAlpha)),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn test_nested_list_items() {
+        let input = "- Parent\n  - Child\n\t1. Grandchild";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::ListItem(vec![Token::Text("Parent".to_string())], None, 0),
+                Token::Newline,
+                Token::ListItem(vec![Token::Text("Child".to_string())], None, 2),
+                Token::Newline,
+                Token::ListItem(
+                    vec![Token::Text("Grandchild".to_string())],
+                    Some((1, Numbering::Decimal)),
+                    4
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blockquote() {
+        let input = "> Quoted text\n> more";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BlockQuote(vec![Token::Text("Quoted text".to_string())], 1),
+                Token::Newline,
+                Token::BlockQuote(vec![Token::Text("more".to_string())], 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_blockquote() {
+        let input = ">outer\n>> inner\n> > also inner";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BlockQuote(vec![Token::Text("outer".to_string())], 1),
+                Token::Newline,
+                Token::BlockQuote(vec![Token::Text("inner".to_string())], 2),
+                Token::Newline,
+                Token::BlockQuote(vec![Token::Text("also inner".to_string())], 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_block_with_language() {
+        let input = "```python\ndef f(x):\n    return  x * 2\nprint(f(2))\n```";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::CodeBlock(
+                "def f(x):\n    return  x * 2\nprint(f(2))".to_string(),
+                Some("python".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_code_block_without_language() {
+        let input = "```\nls -la\n```\nText";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::CodeBlock("ls -la".to_string(), None),
+                Token::Newline,
+                Token::Text("Text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latex_code_block_is_raw_latex() {
+        let input = "```latex\n\\begin{center}\n50% _x_\n\\end{center}\n```";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::RawLatex(
+                "\\begin{center}\n50% _x_\n\\end{center}".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_empty_code_block() {
+        let input = "```\n```";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::CodeBlock(String::new(), None)]);
+    }
+
+    #[test]
+    fn test_unterminated_code_block() {
+        let input = "```sh\necho hi";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Unterminated(
+                Construct::CodeBlock(Some("sh".to_string())),
+                "echo hi".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_code_fence_closes_on_a_bare_run_as_long_as_the_opening() {
+        let mut tokenizer = Tokenizer::new("````md\n```python\nx = 1\n```\n````\nafter");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::CodeBlock("```python\nx = 1\n```".to_string(), Some("md".to_string())),
+                Token::Newline,
+                Token::Text("after".to_string())
+            ]
+        );
+        let mut tokenizer = Tokenizer::new("```rust ignore\nfn f() {}\n```");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![Token::CodeBlock(
+                "fn f() {}".to_string(),
+                Some("rust".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_backtick_in_info_string_is_not_a_fence() {
+        let mut tokenizer = Tokenizer::new("```code```\nmore");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Code("code".to_string()),
+                Token::Newline,
+                Token::Text("more".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_block_keeps_markdown_literal() {
+        let input = "```rust\n# not a header\n**not bold** and *not italic*\n- [x](y)\n```";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::CodeBlock(
+                "# not a header\n**not bold** and *not italic*\n- [x](y)".to_string(),
+                Some("rust".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_indented_code_block() {
+        let input = "Text\n\n    let x = 1;\n\tlet  y = *x*;\n\nMore";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("Text".to_string()),
+                Token::ParagraphBreak,
+                Token::CodeBlock("let x = 1;\nlet  y = *x*;".to_string(), None),
+                Token::ParagraphBreak,
+                Token::Text("More".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indented_code_keeps_inner_blank_lines() {
+        let input = "    fn a() {}\n\n  \n    fn b() {}\n\ntext";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::CodeBlock("fn a() {}\n\n\nfn b() {}".to_string(), None),
+                Token::ParagraphBreak,
+                Token::Text("text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indented_list_marker_is_a_nested_item_not_code() {
+        let input = "- item\n\n    - nested\n\n    code";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::ListItem(vec![Token::Text("item".to_string())], None, 0),
+                Token::ParagraphBreak,
+                Token::ListItem(vec![Token::Text("nested".to_string())], None, 4),
+                Token::ParagraphBreak,
+                Token::CodeBlock("code".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_indented_line_continues_paragraph() {
+        let input = "Text\n    more text";
+        let mut tokenizer = Tokenizer::new(input);
+        assert!(!tokenizer
+            .tokenize()
+            .iter()
+            .any(|token| matches!(token, Token::CodeBlock(..))));
+    }
+
+    #[test]
+    fn test_code_span() {
+        let input = "Call `main()` now";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("Call ".to_string()),
+                Token::Code("main()".to_string()),
+                Token::Text(" now".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_span_with_double_backticks() {
+        let input = "``a ` b`` and `` ` ``";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Code("a ` b".to_string()),
+                Token::Text(" and ".to_string()),
+                Token::Code("`".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_span_preserves_spacing_and_hyphens() {
+        let input = "`a  b` `x - y`";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Code("a  b".to_string()),
+                Token::Text(" ".to_string()),
+                Token::Code("x - y".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_code_span() {
+        let input = "text `code";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::Text("text `code".to_string())]);
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!(collapse_whitespace("a   b".to_string()), "a b");
+        assert_eq!(collapse_whitespace("a \t\tb\tc".to_string()), "a b c");
+        assert_eq!(collapse_whitespace("  edges  ".to_string()), " edges ");
+        assert_eq!(collapse_whitespace("as is".to_string()), "as is");
+    }
+
+    #[test]
+    fn test_whitespace_collapses_outside_code() {
+        let input = "one   two\t\tthree `a   b` $x   y$";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Text("one two three ".to_string()),
+                Token::Code("a   b".to_string()),
+                Token::Text(" ".to_string()),
+                Token::InlineMath("x   y".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_collapses_in_text_that_is_not_a_link() {
+        let mut tokenizer = Tokenizer::new("[a    b] c\t\td");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![Token::Text("[a b] c d".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_hard_break() {
+        let input = "line one  \nline two \nline three";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("line one".to_string()),
+                Token::HardBreak,
+                Token::Text("line two ".to_string()),
+                Token::Newline,
+                Token::Text("line three".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_runs_of_spaces() {
+        let input = "word      word";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::Text("word word".to_string())]);
+    }
+
+    #[test]
+    fn test_horizontal_rule() {
+        let input = "---\ntext";
         let mut tokenizer = Tokenizer::new(input);
         let tokens = tokenizer.tokenize();
         assert_eq!(
             tokens,
-            vec![Token::ListItem("List item".to_string(), false)]
+            vec![
+                Token::HorizontalRule,
+                Token::Newline,
+                Token::Text("text".to_string())
+            ]
         );
     }
 
     #[test]
-    fn test_ordered_list_item() {
-        let input = "1. List item";
+    fn test_html_comments() {
+        let mut tokenizer = Tokenizer::new("a<!-- one\ntwo -->b <!-- open");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Text("a".to_string()),
+                Token::Comment("one\ntwo".to_string()),
+                Token::Text("b ".to_string()),
+                Token::Comment("open".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fenced_div() {
+        let input = "::: center\n# Title\n\n*text*\n:::\n::: {.right}\n:::";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Div(
+                    "center".to_string(),
+                    vec![
+                        Token::Header(vec![Token::Text("Title".to_string())], 1),
+                        Token::ParagraphBreak,
+                        Token::Italic(vec![Token::Text("text".to_string())])
+                    ]
+                ),
+                Token::Newline,
+                Token::Div("right".to_string(), vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_div_is_text() {
+        let mut tokenizer = Tokenizer::new("::: center\ntext");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Text("::: center".to_string()),
+                Token::Newline,
+                Token::Text("text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let mut tokenizer = Tokenizer::new("---\ntitle: Notes\nauthor: 'Jane Doe'\n...\n# Intro");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::FrontMatter(vec![
+                    ("title".to_string(), "Notes".to_string()),
+                    ("author".to_string(), "Jane Doe".to_string())
+                ]),
+                Token::Header(vec![Token::Text("Intro".to_string())], 1)
+            ]
+        );
+        let mut tokenizer = Tokenizer::new("---   \ntitle: X\n---\nBody");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::FrontMatter(vec![("title".to_string(), "X".to_string())]),
+                Token::Text("Body".to_string())
+            ]
+        );
+        let mut tokenizer = Tokenizer::new(
+            "---\n# metadata\ntitle: Notes\ntags:\n  - a\n  - b\nkeywords:\n- c\nauthor: Me\n---\nBody",
+        );
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::FrontMatter(vec![
+                    ("title".to_string(), "Notes".to_string()),
+                    ("author".to_string(), "Me".to_string())
+                ]),
+                Token::Text("Body".to_string())
+            ]
+        );
+        let mut tokenizer = Tokenizer::new("---\ntitle: Notes");
+        assert_eq!(tokenizer.tokenize()[0], Token::HorizontalRule);
+        let mut tokenizer = Tokenizer::new("---\nSome paragraph\n\n---\nafter");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::HorizontalRule,
+                Token::Newline,
+                Token::Text("Some paragraph".to_string()),
+                Token::ParagraphBreak,
+                Token::HorizontalRule,
+                Token::Newline,
+                Token::Text("after".to_string())
+            ]
+        );
+        let mut tokenizer = Tokenizer::new("text\n---\ntitle: Notes\n---");
+        assert!(!tokenizer
+            .tokenize()
+            .iter()
+            .any(|token| matches!(token, Token::FrontMatter(_))));
+    }
+
+    #[test]
+    fn test_horizontal_rule_styles() {
+        for input in ["---", "***", "___", "* * *", "_____"] {
+            let mut tokenizer = Tokenizer::new(input);
+            let tokens = tokenizer.tokenize();
+            assert_eq!(tokens, vec![Token::HorizontalRule], "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_two_dashes_are_not_a_rule() {
+        let input = "--";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert!(!tokens.contains(&Token::HorizontalRule));
+    }
+
+    #[test]
+    fn test_dashes_followed_by_text_are_not_a_rule() {
+        let input = "--- some text";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert!(!tokens.contains(&Token::HorizontalRule));
+    }
+
+    #[test]
+    fn test_multi_digit_ordered_list_items() {
+        let input = "10. ten\n100. hundred\n0. zero";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::ListItem(
+                    vec![Token::Text("ten".to_string())],
+                    Some((10, Numbering::Decimal)),
+                    0
+                ),
+                Token::Newline,
+                Token::ListItem(
+                    vec![Token::Text("hundred".to_string())],
+                    Some((100, Numbering::Decimal)),
+                    0
+                ),
+                Token::Newline,
+                Token::ListItem(
+                    vec![Token::Text("zero".to_string())],
+                    Some((0, Numbering::Decimal)),
+                    0
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_number_without_marker_is_text() {
+        let input = "42 is the answer";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens, vec![Token::Text("42 is the answer".to_string())]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "Intro\n\n## Header on line 3\n- **item**";
+        let mut tokenizer = Tokenizer::new(input);
+        let starts: Vec<(usize, usize)> = tokenizer
+            .tokenize_spanned()
+            .iter()
+            .map(|spanned| (spanned.span.start_line, spanned.span.start_col))
+            .collect();
+        assert_eq!(starts, vec![(1, 1), (1, 6), (3, 1), (3, 20), (4, 1)]);
+    }
+
+    #[test]
+    fn test_header_line_number() {
+        let input = "first\nsecond\n# Third";
+        let mut tokenizer = Tokenizer::new(input);
+        let header = tokenizer.tokenize_spanned().pop().unwrap();
+        assert_eq!(
+            header.node,
+            Token::Header(vec![Token::Text("Third".to_string())], 1)
+        );
+        assert_eq!((header.span.start_line, header.span.start_col), (3, 1));
+    }
+
+    #[test]
+    fn test_header_and_list_item_spans() {
+        let input = "# Title\ntext\n- item";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize_spanned();
+        assert_eq!(
+            tokens[0].node,
+            Token::Header(vec![Token::Text("Title".to_string())], 1)
+        );
+        assert_eq!(
+            tokens[0].span,
+            Span {
+                start_line: 1,
+                start_col: 1,
+                end_line: 1,
+                end_col: 8
+            }
+        );
+        assert_eq!(
+            tokens[4].node,
+            Token::ListItem(vec![Token::Text("item".to_string())], None, 0)
+        );
+        assert_eq!(
+            tokens[4].span,
+            Span {
+                start_line: 3,
+                start_col: 1,
+                end_line: 3,
+                end_col: 7
+            }
+        );
+    }
+
+    #[test]
+    fn test_spans_count_characters_not_bytes() {
+        let input = "Café 日本 🎉\n# Menü";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize_spanned();
+        assert_eq!(tokens[0].node, Token::Text("Café 日本 🎉".to_string()));
+        assert_eq!((tokens[0].span.end_line, tokens[0].span.end_col), (1, 10));
+        assert_eq!(
+            tokens[2].node,
+            Token::Header(vec![Token::Text("Menü".to_string())], 1)
+        );
+        assert_eq!(
+            tokens[2].span,
+            Span {
+                start_line: 2,
+                start_col: 1,
+                end_line: 2,
+                end_col: 7
+            }
+        );
+    }
+
+    #[test]
+    fn test_checked_tokenizing_reports_unclosed_emphasis() {
+        let input = "Intro\nsome **unterminated";
+        let error = Tokenizer::new(input).tokenize_checked().unwrap_err();
+        assert_eq!(
+            error.node,
+            ConvertError::UnterminatedEmphasis("**".to_string())
+        );
+        assert_eq!((error.span.start_line, error.span.start_col), (2, 6));
+    }
+
+    #[test]
+    fn test_lenient_tokenizing_recovers_from_unclosed_emphasis() {
+        let input = "**unterminated";
+        assert_eq!(
+            Tokenizer::new(input).tokenize(),
+            vec![
+                Token::Unterminated(Construct::Emphasis, "**".to_string()),
+                Token::Text("unterminated".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checked_tokenizing_reports_unclosed_code_span_and_link() {
+        let error = Tokenizer::new("text `code").tokenize_checked().unwrap_err();
+        assert_eq!(error.node, ConvertError::UnterminatedCode("`".to_string()));
+        assert_eq!(error.span.start_col, 6);
+        let error = Tokenizer::new("- see [link](...")
+            .tokenize_checked()
+            .unwrap_err();
+        assert_eq!(
+            error.node,
+            ConvertError::UnterminatedLink("[link](...".to_string())
+        );
+    }
+
+    #[test]
+    fn test_checked_tokenizing_accepts_well_formed_input() {
+        let input = "**bold**, `code` and [a](b)";
+        assert_eq!(
+            Tokenizer::new(input).tokenize_checked(),
+            Ok(Tokenizer::new(input).tokenize())
+        );
+    }
+
+    #[test]
+    fn test_newline_span_ends_on_next_line() {
+        let input = "a\nb";
+        let mut tokenizer = Tokenizer::new(input);
+        let newline = &tokenizer.tokenize_spanned()[1];
+        assert_eq!(newline.node, Token::Newline);
+        assert_eq!((newline.span.end_line, newline.span.end_col), (2, 1));
+    }
+
+    #[test]
+    fn test_definition_item_with_two_definitions() {
+        let input = "Rust\n: A language\n:   An oxide\n\nText";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::DefinitionItem {
+                    term: vec![Token::Text("Rust".to_string())],
+                    definitions: vec![
+                        vec![Token::Text("A language".to_string())],
+                        vec![Token::Text("An oxide".to_string())]
+                    ]
+                },
+                Token::ParagraphBreak,
+                Token::Text("Text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_definition_may_follow_its_term_after_a_blank_line() {
+        let expected = vec![Token::DefinitionItem {
+            term: vec![Token::Text("Term".to_string())],
+            definitions: vec![vec![Token::Text("Meaning".to_string())]],
+        }];
+        assert_eq!(Tokenizer::new("Term\n: Meaning").tokenize(), expected);
+        assert_eq!(Tokenizer::new("Term\n\n: Meaning").tokenize(), expected);
+    }
+
+    #[test]
+    fn test_colon_line_after_paragraph_text_is_not_a_definition() {
+        let input = "Some text\nmore text\n: not a definition";
+        let mut tokenizer = Tokenizer::new(input);
+        assert!(!tokenizer
+            .tokenize()
+            .iter()
+            .any(|token| matches!(token, Token::DefinitionItem { .. })));
+    }
+
+    #[test]
+    fn test_table() {
+        let input = "| A | B |\n|---|---|\n| 1 | 2 |\n| 3 | 4 |\nafter";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Table {
+                    headers: vec!["A".to_string(), "B".to_string()],
+                    rows: vec![
+                        vec!["1".to_string(), "2".to_string()],
+                        vec!["3".to_string(), "4".to_string()]
+                    ],
+                    alignments: vec![Align::Left, Align::Left],
+                },
+                Token::Newline,
+                Token::Text("after".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_alignments() {
+        let input = "a | b | c\n:--- | :---: | ---:";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Table {
+                headers: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+                rows: vec![],
+                alignments: vec![Align::Left, Align::Center, Align::Right],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ragged_table_rows() {
+        let input = "| a | b |\n|---|---|\n| 1 |\n| 2 | 3 | 4 |";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![Token::Table {
+                headers: vec!["a".to_string(), "b".to_string()],
+                rows: vec![
+                    vec!["1".to_string(), String::new()],
+                    vec!["2".to_string(), "3".to_string()]
+                ],
+                alignments: vec![Align::Left, Align::Left],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pipe_without_delimiter_row_is_text() {
+        let input = "a | b\nc | d";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("a | b".to_string()),
+                Token::Newline,
+                Token::Text("c | d".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backslash_hard_break() {
+        let input = "one\\\ntwo";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("one".to_string()),
+                Token::HardBreak,
+                Token::Text("two".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blank_lines_are_one_paragraph_break() {
+        let input = "one\ntwo\n\n  \n\nthree  \n\nfour";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("one".to_string()),
+                Token::Newline,
+                Token::Text("two".to_string()),
+                Token::ParagraphBreak,
+                Token::Text("three ".to_string()),
+                Token::ParagraphBreak,
+                Token::Text("four".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_and_blank_input_has_no_tokens() {
+        for input in ["", "   ", "\n\n", " \t\n  \n"] {
+            assert_eq!(Tokenizer::new(input).tokenize(), vec![], "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_whitespace_only_line_is_an_empty_line() {
+        let input = "```\ncode\n```\n   \ntext\n   ";
         let mut tokenizer = Tokenizer::new(input);
         let tokens = tokenizer.tokenize();
-        assert_eq!(tokens, vec![Token::ListItem("List item".to_string(), true)]);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::CodeBlock("code".to_string(), None),
+                Token::ParagraphBreak,
+                Token::Text("text".to_string()),
+                Token::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leading_blank_lines_do_not_shift_spans() {
+        let input = "\n\n**x";
+        let error = Tokenizer::new(input).tokenize_checked().unwrap_err();
+        assert_eq!((error.span.start_line, error.span.start_col), (3, 1));
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let lf = "# Title\ntext\n\n- item\n```\ncode\n```";
+        let crlf = lf.replace('\n', "\r\n");
+        let cr = lf.replace('\n', "\r");
+        assert_eq!(
+            Tokenizer::new(&crlf).tokenize(),
+            Tokenizer::new(lf).tokenize()
+        );
+        assert_eq!(
+            Tokenizer::new(&cr).tokenize(),
+            Tokenizer::new(lf).tokenize()
+        );
+    }
+
+    #[test]
+    fn test_crlf_header() {
+        let mut tokenizer = Tokenizer::new("# Title\r\ntext");
+        assert_eq!(
+            tokenizer.tokenize(),
+            vec![
+                Token::Header(vec![Token::Text("Title".to_string())], 1),
+                Token::Newline,
+                Token::Text("text".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        let input = "Energy $E=mc^2$ costs $5 and $10.\n$$\n\\sum_i x_i\n$$";
+        let mut tokenizer = Tokenizer::new(input);
+        let tokens = tokenizer.tokenize();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("Energy ".to_string()),
+                Token::InlineMath("E=mc^2".to_string()),
+                Token::Text(" costs $5 and $10.".to_string()),
+                Token::Newline,
+                Token::DisplayMath("\\sum_i x_i".to_string())
+            ]
+        );
     }
 
     #[test]
@@ -0,0 +1,182 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("markdown_to_latex_{}_{}", process::id(), name))
+}
+
+fn binary() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_markdown_to_latex"))
+}
+
+#[test]
+fn converts_input_to_explicit_output() {
+    let input = temp_path("explicit.md");
+    let output = temp_path("explicit_out.tex");
+    fs::write(&input, "# Title\n").unwrap();
+
+    let status = binary()
+        .arg(&input)
+        .arg(&output)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let latex = fs::read_to_string(&output).unwrap();
+    assert!(latex.contains("\\section{Title}"));
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn derives_output_path_from_input() {
+    let input = temp_path("derived.md");
+    fs::write(&input, "**bold**\n").unwrap();
+
+    let status = binary().arg(&input).stdin(Stdio::null()).status().unwrap();
+
+    assert!(status.success());
+    let output = input.with_extension("tex");
+    assert!(fs::read_to_string(&output)
+        .unwrap()
+        .contains("\\textbf{bold}"));
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn missing_input_prints_usage() {
+    let result = binary()
+        .arg(temp_path("does_not_exist.md"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Usage:"));
+}
+
+#[test]
+fn unwritable_output_fails() {
+    let input = temp_path("unwritable.md");
+    fs::write(&input, "# Title\n").unwrap();
+
+    // The input is a file, so no directory can be created under it.
+    let output = binary()
+        .arg(&input)
+        .arg(input.join("out.tex"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Error: "));
+    fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn starred_sections_flag_suppresses_numbering() {
+    let input = temp_path("starred.md");
+    let output = temp_path("starred_out.tex");
+    fs::write(&input, "# Title\n").unwrap();
+
+    let status = binary()
+        .arg("--starred-sections")
+        .arg(&input)
+        .arg(&output)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let latex = fs::read_to_string(&output).unwrap();
+    assert!(latex.contains("\\section*{Title}"));
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn tokens_flag_prints_tokens_to_stderr() {
+    let input = temp_path("tokens.md");
+    fs::write(&input, "# Title\n").unwrap();
+
+    let output = binary()
+        .arg("--tokens")
+        .arg(&input)
+        .arg("-")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stderr.contains("Header("));
+    assert!(!stdout.contains("Header("));
+    assert!(stdout.contains("\\section{Title}"));
+    fs::remove_file(input).unwrap();
+}
+
+#[test]
+fn smart_quotes_flag_curls_quotes() {
+    let input = temp_path("quotes.md");
+    let output = temp_path("quotes_out.tex");
+    fs::write(&input, "\"Hello\" and 'hi'\n").unwrap();
+
+    let status = binary()
+        .arg("--smart-quotes")
+        .arg(&input)
+        .arg(&output)
+        .stdin(Stdio::null())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    let latex = fs::read_to_string(&output).unwrap();
+    assert!(latex.contains("``Hello'' and `hi'"));
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output).unwrap();
+}
+
+#[test]
+fn unknown_option_prints_usage() {
+    let result = binary()
+        .arg("--bogus")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert_eq!(result.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Usage:"));
+}
+
+#[test]
+fn stdout_matches_convert_str() {
+    let input = "# Title\n\nText with [a link](http://example.com).\n";
+    let mut child = binary()
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let result = child.wait_with_output().unwrap();
+
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        markdown_to_latex::convert_str(input)
+    );
+}
@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use markdown_to_latex::file_utils::write_to_file;
+use markdown_to_latex::{
+    convert_str, markdown_to_latex, tokenize, tokens_debug_string, LatexConverter, Token, Tokenizer,
+};
+
+#[test]
+fn converts_small_document() {
+    let input = "# Title\n\nSome **bold** text.\n\n- one\n- two";
+    assert_eq!(
+        markdown_to_latex(input),
+        "\\section{Title}\n\n\nSome \\textbf{bold} text.\n\n\\begin{itemize}\n\\item one\n\\item two\\end{itemize}\n"
+    );
+}
+
+#[test]
+fn public_types_compose() {
+    let mut tokenizer = Tokenizer::new("*hi*");
+    let tokens = tokenizer.tokenize();
+    assert_eq!(
+        tokens,
+        vec![Token::Italic(vec![Token::Text("hi".to_string())])]
+    );
+    assert_eq!(LatexConverter::convert(tokens), "\\textit{hi}");
+}
+
+#[test]
+fn blank_input_converts_to_nothing() {
+    for input in ["", "   ", "\n\n"] {
+        assert_eq!(markdown_to_latex(input), "", "{:?}", input);
+    }
+    assert!(convert_str("   \n").contains("\\begin{document}\n\\end{document}\n"));
+}
+
+#[test]
+fn tokenize_matches_the_tokenizer() {
+    let input = "# Title\n\n- **bold** item\n\nText with `code`.";
+    assert_eq!(tokenize(input), Tokenizer::new(input).tokenize());
+    assert_eq!(
+        tokenize("a [b](c)"),
+        vec![
+            Token::Text("a ".to_string()),
+            Token::Link("b".to_string(), "c".to_string(), None)
+        ]
+    );
+}
+
+#[test]
+fn tokens_debug_string_shows_the_tokens() {
+    let debug = tokens_debug_string("# Title\n\nSome *text*");
+    assert!(debug.contains("Header("));
+    assert!(debug.contains("Italic("));
+    assert!(debug.contains("\"Title\""));
+}
+
+#[test]
+fn convert_str_builds_a_document_in_memory() {
+    let latex = convert_str("# Hi");
+    assert!(latex.starts_with("\\documentclass{article}\n"));
+    assert!(latex.contains("\\section{Hi}"));
+    assert!(latex.ends_with("\\end{document}\n"));
+}
+
+#[test]
+fn write_to_file_creates_parent_directories() {
+    let root = env::temp_dir().join(format!("markdown_to_latex_{}_nested", process::id()));
+    let path = root.join("out").join("sub").join("doc.tex");
+
+    write_to_file("\\section{A}\n".to_string(), path.to_str().unwrap()).unwrap();
+
+    assert!(path.is_file());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "\\section{A}\n");
+    fs::remove_dir_all(root).unwrap();
+}